@@ -0,0 +1,82 @@
+//! Decodes arbitrary bytes. Nothing may panic, and whatever decodes has to survive a
+//! trip through the assembler at the same length: `cargo +nightly fuzz run decode`.
+#![no_main]
+
+use computer_enhance::{assembler, instruction::Instruction, opcode::Opcode};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(instruction) = Instruction::try_from(data) else {
+        return;
+    };
+    let size = instruction.size as usize;
+    assert!(
+        size <= data.len(),
+        "decoded {} bytes of {}",
+        size,
+        data.len()
+    );
+    // nasm can't write a prefix twice, and dropping the extra ones can leave a short
+    // jump's target out of reach, so those instructions only have to decode.
+    if has_repeated_prefix(&data[..size]) {
+        return;
+    }
+
+    // The re-encoding has to be as long as the input, with one exception: like nasm,
+    // the assembler picks the shortest encoding, so an instruction spelled a longer
+    // way, with a zero displacement or an alias opcode, comes back shorter. It still
+    // has to be the same instruction, and that shorter form has to be canonical: the
+    // decoder reads exactly those bytes, and they re-encode to themselves.
+    let text = instruction.to_asm();
+    let bytes = assembler::assemble(&text)
+        .unwrap_or_else(|err| panic!("{:02x?} decoded to {:?}: {:?}", &data[..size], text, err));
+    let again = Instruction::try_from(&bytes[..]).unwrap();
+    if bytes.len() != size {
+        assert!(bytes.len() < size, "{:?} grew to {:02x?}", text, bytes);
+        assert!(
+            same_instruction(&again, &instruction),
+            "{:02x?} shrank into a different instruction, {:02x?}",
+            &data[..size],
+            bytes
+        );
+    }
+    assert_eq!(
+        again.size as usize,
+        bytes.len(),
+        "{:?} as {:02x?}",
+        text,
+        bytes
+    );
+    assert_eq!(
+        assembler::assemble(&again.to_asm()).unwrap(),
+        bytes,
+        "{:?}",
+        text
+    );
+});
+
+/// Whether a lock, a rep or a segment override shows up more than once before the
+/// opcode.
+fn has_repeated_prefix(bytes: &[u8]) -> bool {
+    let mut seen = [false; 3];
+    for &byte in bytes {
+        let kind = match byte {
+            0xf0 => 0,
+            0xf2 | 0xf3 => 1,
+            0x26 | 0x2e | 0x36 | 0x3e => 2,
+            _ => return false,
+        };
+        if std::mem::replace(&mut seen[kind], true) {
+            return true;
+        }
+    }
+    false
+}
+
+/// Whether `a` and `b` do the same thing. The short form of xchg always names the
+/// accumulator second, but xchg doesn't care which operand comes first.
+fn same_instruction(a: &Instruction, b: &Instruction) -> bool {
+    let mut swapped = a.clone();
+    swapped.operands.swap(0, 1);
+    a.to_asm() == b.to_asm() || (a.opcode == Opcode::Xchg && swapped.to_asm() == b.to_asm())
+}
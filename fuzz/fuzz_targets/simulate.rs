@@ -0,0 +1,56 @@
+//! Runs arbitrary programs for a bounded number of steps. Nothing may panic, and
+//! unless an instruction can transfer control, execution has to carry on right
+//! after it: `cargo +nightly fuzz run simulate`.
+#![no_main]
+
+use computer_enhance::{
+    instruction::Instruction, memory::MEMORY_SIZE, opcode::Opcode, operand::Operand,
+    register::Register, simulator::Simulator,
+};
+use libfuzzer_sys::fuzz_target;
+
+/// Far more than any part 1 listing needs, and still quick to run out.
+const MAX_STEPS: usize = 10_000;
+
+fuzz_target!(|program: &[u8]| {
+    let mut simulator = Simulator::new();
+    simulator.load(&program[..program.len().min(MEMORY_SIZE)]);
+    for _ in 0..MAX_STEPS {
+        let (cs, ip) = (simulator.registers.read(Register::CS), simulator.ip);
+        // Undecodable bytes, unimplemented instructions and hlt all end the run.
+        let Ok((instruction, _)) = simulator.step() else {
+            break;
+        };
+        if transfers_control(&instruction) {
+            continue;
+        }
+        assert_eq!(
+            (simulator.registers.read(Register::CS), simulator.ip),
+            (cs, ip.wrapping_add(instruction.bytes() as u16)),
+            "{} didn't continue after itself",
+            instruction.to_asm()
+        );
+    }
+});
+
+/// Whether `instruction` can leave cs:ip anywhere but right after itself: jumps,
+/// calls, returns, interrupts, the divisions that raise interrupt 0, and anything
+/// that writes cs.
+fn transfers_control(instruction: &Instruction) -> bool {
+    matches!(
+        instruction.opcode,
+        Opcode::Jmp
+            | Opcode::Call
+            | Opcode::Ret
+            | Opcode::Retf
+            | Opcode::Iret
+            | Opcode::Int
+            | Opcode::Int3
+            | Opcode::Into
+            | Opcode::Div
+            | Opcode::Idiv
+    ) || instruction
+        .operands()
+        .any(|operand| matches!(operand, Operand::RelativeJump(_)))
+        || instruction.operands[0] == Some(Operand::Register(Register::CS))
+}
@@ -0,0 +1,246 @@
+//! The arithmetic the simulator performs, returning results along with the flags they
+//! produce so callers can decide which flags to keep.
+
+use crate::{flags::Flags, opcode::Opcode};
+
+pub fn sign_bit(wide: bool) -> u32 {
+    if wide {
+        0x8000
+    } else {
+        0x80
+    }
+}
+
+pub fn mask(wide: bool) -> u32 {
+    if wide {
+        0xffff
+    } else {
+        0xff
+    }
+}
+
+/// Zero, sign and parity for a result. Parity only ever looks at the low byte.
+pub fn result_flags(result: u16, wide: bool) -> Flags {
+    let result = result as u32 & mask(wide);
+    let mut flags = Flags::empty();
+    flags.set(Flags::ZERO, result == 0);
+    flags.set(Flags::SIGN, result & sign_bit(wide) != 0);
+    flags.set(Flags::PARITY, (result as u8).count_ones().is_multiple_of(2));
+    flags
+}
+
+/// `a + b + carry`, for add and adc.
+pub fn add(a: u16, b: u16, carry: bool, wide: bool) -> (u16, Flags) {
+    let (a, b, c) = (a as u32 & mask(wide), b as u32 & mask(wide), carry as u32);
+    let result = a + b + c;
+    let mut flags = result_flags(result as u16, wide);
+    flags.set(Flags::CARRY, result > mask(wide));
+    flags.set(Flags::AUX_CARRY, (a & 0xf) + (b & 0xf) + c > 0xf);
+    flags.set(
+        Flags::OVERFLOW,
+        !(a ^ b) & (a ^ result) & sign_bit(wide) != 0,
+    );
+    (result as u16, flags)
+}
+
+/// `a - b - borrow`, for sub, sbb and cmp.
+pub fn sub(a: u16, b: u16, borrow: bool, wide: bool) -> (u16, Flags) {
+    let (a, b, c) = (a as u32 & mask(wide), b as u32 & mask(wide), borrow as u32);
+    let result = a.wrapping_sub(b).wrapping_sub(c);
+    let mut flags = result_flags(result as u16, wide);
+    flags.set(Flags::CARRY, a < b + c);
+    flags.set(Flags::AUX_CARRY, (a & 0xf) < (b & 0xf) + c);
+    flags.set(
+        Flags::OVERFLOW,
+        (a ^ b) & (a ^ result) & sign_bit(wide) != 0,
+    );
+    (result as u16, flags)
+}
+
+/// Shifts or rotates `value` by `count` bits, one at a time the way the 8086 does.
+/// Returns the result, the flags it produces, and which of those flags it defines:
+/// rotates leave everything but CF and OF alone, and OF is only defined for a count
+/// of one.
+pub fn shift(op: Opcode, value: u16, count: u8, carry: bool, wide: bool) -> (u16, Flags, Flags) {
+    let (mask, sign) = (mask(wide), sign_bit(wide));
+    let original = value as u32 & mask;
+    let (mut value, mut carry) = (original, carry);
+    for _ in 0..count {
+        let (high, low) = (value & sign != 0, value & 1 != 0);
+        value = match op {
+            Opcode::Shl => value << 1,
+            Opcode::Shr => value >> 1,
+            Opcode::Sar => (value >> 1) | (value & sign),
+            Opcode::Rol => (value << 1) | high as u32,
+            Opcode::Ror => (value >> 1) | if low { sign } else { 0 },
+            Opcode::Rcl => (value << 1) | carry as u32,
+            Opcode::Rcr => (value >> 1) | if carry { sign } else { 0 },
+            _ => unreachable!("{} isn't a shift", op),
+        } & mask;
+        carry = match op {
+            Opcode::Shl | Opcode::Rol | Opcode::Rcl => high,
+            _ => low,
+        };
+    }
+
+    let top = |value: u32| value & sign != 0;
+    let overflow = match op {
+        Opcode::Shl | Opcode::Rol | Opcode::Rcl => top(value) != carry,
+        Opcode::Shr => top(original),
+        Opcode::Sar => false,
+        _ => top(value) != top(value << 1),
+    };
+    let mut flags = result_flags(value as u16, wide);
+    flags.set(Flags::CARRY, carry);
+    flags.set(Flags::OVERFLOW, overflow);
+
+    let mut defined = match (count, op) {
+        (0, _) => Flags::empty(),
+        (_, Opcode::Shl | Opcode::Shr | Opcode::Sar) => {
+            Flags::CARRY | Flags::ZERO | Flags::SIGN | Flags::PARITY
+        }
+        _ => Flags::CARRY,
+    };
+    if count == 1 {
+        defined = defined | Flags::OVERFLOW;
+    }
+    (value as u16, flags, defined)
+}
+
+/// `a * b` for mul and imul, as the double width product. CF and OF are set when the
+/// upper half holds significant bits, that is, more than the extended sign for imul.
+pub fn multiply(a: u16, b: u16, signed: bool, wide: bool) -> (u32, Flags) {
+    let bits = if wide { 16 } else { 8 };
+    let (a, b) = (a as u32 & mask(wide), b as u32 & mask(wide));
+    let (product, significant) = if signed {
+        let product = (sign_extend(a, wide) * sign_extend(b, wide)) as u32;
+        let low = product & mask(wide);
+        let extended = sign_extend(low, wide) as u32;
+        (
+            product,
+            product & double_mask(wide) != extended & double_mask(wide),
+        )
+    } else {
+        let product = a * b;
+        (product, product >> bits != 0)
+    };
+    let mut flags = Flags::empty();
+    flags.set(Flags::CARRY | Flags::OVERFLOW, significant);
+    (product & double_mask(wide), flags)
+}
+
+/// The quotient and remainder of `dividend / divisor` for div and idiv, or `None` when
+/// the divisor is zero or the quotient doesn't fit, which the 8086 reports as
+/// interrupt 0. The remainder takes the sign of the dividend.
+///
+/// The 8086 rejects the most negative quotient too, so idiv only produces -127 to 127
+/// for bytes and -32767 to 32767 for words.
+pub fn divide(dividend: u32, divisor: u16, signed: bool, wide: bool) -> Option<(u16, u16)> {
+    let divisor = divisor as u32 & mask(wide);
+    if divisor == 0 {
+        return None;
+    }
+    let dividend = dividend & double_mask(wide);
+    let (quotient, remainder, fits) = if signed {
+        let dividend = if wide {
+            dividend as i32 as i64
+        } else {
+            dividend as u16 as i16 as i64
+        };
+        let divisor = sign_extend(divisor, wide) as i64;
+        let limit = sign_bit(wide) as i64 - 1;
+        let quotient = dividend / divisor;
+        (quotient, dividend % divisor, quotient.abs() <= limit)
+    } else {
+        let quotient = dividend / divisor;
+        (
+            quotient as i64,
+            (dividend % divisor) as i64,
+            quotient <= mask(wide),
+        )
+    };
+    fits.then_some((quotient as u16, remainder as u16))
+}
+
+/// The ASCII and decimal adjustments, applied to ax. Returns the new ax, the flags it
+/// produces, and which of those flags the instruction defines. aam and aad only use
+/// base 10, the only one the manual lists an encoding for.
+pub fn adjust(op: Opcode, ax: u16, flags: Flags) -> (u16, Flags, Flags) {
+    let (mut al, mut ah) = (ax as u8, (ax >> 8) as u8);
+    let (carry, aux_carry) = (
+        flags.contains(Flags::CARRY),
+        flags.contains(Flags::AUX_CARRY),
+    );
+    let low_digit_overflow = al & 0xf > 9 || aux_carry;
+    let mut out = Flags::empty();
+    let defined = match op {
+        Opcode::Aaa | Opcode::Aas => {
+            if low_digit_overflow {
+                if op == Opcode::Aaa {
+                    al = al.wrapping_add(6);
+                    ah = ah.wrapping_add(1);
+                } else {
+                    al = al.wrapping_sub(6);
+                    ah = ah.wrapping_sub(1);
+                }
+            }
+            al &= 0xf;
+            out.set(Flags::CARRY | Flags::AUX_CARRY, low_digit_overflow);
+            Flags::CARRY | Flags::AUX_CARRY
+        }
+        Opcode::Daa | Opcode::Das => {
+            let original = al;
+            let mut carry_out = false;
+            if low_digit_overflow {
+                let (value, overflow) = if op == Opcode::Daa {
+                    al.overflowing_add(6)
+                } else {
+                    al.overflowing_sub(6)
+                };
+                al = value;
+                carry_out = carry || overflow;
+            }
+            if original > 0x99 || carry {
+                al = if op == Opcode::Daa {
+                    al.wrapping_add(0x60)
+                } else {
+                    al.wrapping_sub(0x60)
+                };
+                carry_out = true;
+            }
+            out = result_flags(al as u16, false);
+            out.set(Flags::CARRY, carry_out);
+            out.set(Flags::AUX_CARRY, low_digit_overflow);
+            Flags::ARITHMETIC & !Flags::OVERFLOW
+        }
+        Opcode::Aam => {
+            (ah, al) = (al / 10, al % 10);
+            out = result_flags(al as u16, false);
+            Flags::ZERO | Flags::SIGN | Flags::PARITY
+        }
+        Opcode::Aad => {
+            (ah, al) = (0, ah.wrapping_mul(10).wrapping_add(al));
+            out = result_flags(al as u16, false);
+            Flags::ZERO | Flags::SIGN | Flags::PARITY
+        }
+        _ => unreachable!("{} isn't an adjustment", op),
+    };
+    (al as u16 | (ah as u16) << 8, out, defined)
+}
+
+fn sign_extend(value: u32, wide: bool) -> i32 {
+    if wide {
+        value as u16 as i16 as i32
+    } else {
+        value as u8 as i8 as i32
+    }
+}
+
+/// The mask for a product or dividend, twice the operand size.
+fn double_mask(wide: bool) -> u32 {
+    if wide {
+        0xffff_ffff
+    } else {
+        0xffff
+    }
+}
@@ -0,0 +1,450 @@
+//! Encodes instructions back into bytes using the same table the decoder reads.
+//!
+//! When several encodings can express an instruction the shortest one wins, and ties
+//! go to whichever comes first in the table. That matches the choices nasm makes for
+//! the listings in `perfaware/part1`, so they assemble byte for byte.
+
+use std::{collections::HashMap, fmt::Display};
+
+use crate::{
+    instruction::{Instruction, Repeat},
+    opcode::Opcode,
+    operand::Operand,
+    parser::{self, ParsedInstruction, ParsedOperand, Statement, Target},
+    register::Register,
+    table::{self, Encoding, Field, Slot, SLOT_COUNT},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssembleError {
+    /// One based line of the source the error came from, or 0 when there is none.
+    pub line: usize,
+    pub msg: String,
+}
+
+impl AssembleError {
+    pub fn new(line: usize, msg: impl Into<String>) -> Self {
+        Self {
+            line,
+            msg: msg.into(),
+        }
+    }
+}
+
+impl Display for AssembleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.msg)
+        } else {
+            write!(f, "line {}: {}", self.line, self.msg)
+        }
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+/// Label addresses depend on the size of the jumps in front of them, so assembly is
+/// repeated until they stop moving.
+const MAX_PASSES: usize = 16;
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    let statements = parser::parse(source)?;
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    for pass in 0..MAX_PASSES {
+        let mut bytes = Vec::new();
+        let mut next_labels = HashMap::new();
+        for statement in &statements {
+            match statement {
+                Statement::Label(name) => {
+                    next_labels.insert(name.as_str(), bytes.len());
+                }
+                Statement::Instruction(parsed) => {
+                    // Forward labels are unknown on the first pass; pretend they're here.
+                    let resolve = |name: &str| match labels.get(name) {
+                        Some(&address) => Ok(address),
+                        None if pass == 0 => Ok(bytes.len()),
+                        None => Err(AssembleError::new(
+                            parsed.line,
+                            format!("undefined label `{}`", name),
+                        )),
+                    };
+                    let encoded = encode_parsed(parsed, bytes.len(), resolve)?;
+                    bytes.extend(encoded);
+                }
+            }
+        }
+        if pass > 0 && next_labels == labels {
+            return Ok(bytes);
+        }
+        labels = next_labels;
+    }
+    Err(AssembleError::new(0, "label addresses never settled"))
+}
+
+fn encode_parsed(
+    parsed: &ParsedInstruction,
+    address: usize,
+    resolve: impl Fn(&str) -> Result<usize, AssembleError>,
+) -> Result<Vec<u8>, AssembleError> {
+    let out_of_range = || AssembleError::new(parsed.line, "jump target is out of range");
+    // The jump offset from the start of the instruction, if there is one.
+    let mut offset = None;
+    let mut operands = [None; 2];
+    for (slot, operand) in operands.iter_mut().zip(&parsed.operands) {
+        *slot = Some(match operand {
+            ParsedOperand::Operand(operand) => *operand,
+            ParsedOperand::Target(target) => {
+                let relative = match target {
+                    Target::Relative(relative) => *relative,
+                    Target::Absolute(absolute) => absolute
+                        .checked_sub(address as i32)
+                        .ok_or_else(out_of_range)?,
+                    Target::Label(name) => resolve(name)? as i32 - address as i32,
+                };
+                offset = Some(relative);
+                Operand::RelativeJump(0)
+            }
+        });
+    }
+    if parsed.operands.len() > operands.len() {
+        return Err(AssembleError::new(parsed.line, "too many operands"));
+    }
+
+    let mut instruction = Instruction {
+        opcode: parsed.opcode,
+        operands,
+        wide: parsed.wide,
+        far: parsed.far,
+        lock: parsed.lock,
+        repeat: parsed.repeat,
+        segment: parsed.segment,
+        size: 0,
+    };
+    let Some(offset) = offset else {
+        return encode(&instruction).map_err(|err| AssembleError::new(parsed.line, err.msg));
+    };
+
+    // The encoded displacement is relative to the end of the instruction, so try each
+    // size until the displacement it implies produces an encoding of that size.
+    for size in 2..=6 {
+        // ip wraps around within its segment, and so does a displacement added to it.
+        let disp = offset.checked_sub(size).ok_or_else(out_of_range)? as i16;
+        for operand in instruction.operands.iter_mut().flatten() {
+            if let Operand::RelativeJump(_) = operand {
+                *operand = Operand::RelativeJump(disp);
+            }
+        }
+        if let Ok(bytes) = encode(&instruction) {
+            if bytes.len() == size as usize {
+                return Ok(bytes);
+            }
+        }
+    }
+    Err(out_of_range())
+}
+
+/// Encodes a single instruction. Relative jumps are taken to be relative to the end
+/// of the encoding that gets picked.
+pub fn encode(instruction: &Instruction) -> Result<Vec<u8>, AssembleError> {
+    // test only has a memory-first encoding and xchg a register-first one, but since
+    // test doesn't write anything and xchg writes both, the operands can go either way
+    // around. The order as written wins a tie.
+    let mut swapped = instruction.clone();
+    swapped.operands.swap(0, 1);
+    let variants = if matches!(instruction.opcode, Opcode::Test | Opcode::Xchg) {
+        vec![instruction, &swapped]
+    } else {
+        vec![instruction]
+    };
+    table::ENCODINGS
+        .iter()
+        .filter(|encoding| encoding.opcode == instruction.opcode)
+        .flat_map(|encoding| {
+            variants
+                .iter()
+                .filter_map(|instruction| try_encoding(encoding, instruction))
+        })
+        .min_by_key(Vec::len)
+        .ok_or(AssembleError::new(
+            0,
+            format!(
+                "no encoding of `{}` takes these operands",
+                instruction.opcode
+            ),
+        ))
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Role {
+    Reg,
+    Rm,
+    Data,
+    RelJmp,
+    Intersegment,
+    Count,
+    Port,
+}
+
+fn try_encoding(encoding: &Encoding, instruction: &Instruction) -> Option<Vec<u8>> {
+    let mut bits = [None; SLOT_COUNT];
+    let mut implicit = [None; SLOT_COUNT];
+    let (mut has_disp, mut has_addr, mut rel_jmp, mut has_data, mut data_if_w) =
+        (false, false, false, false, false);
+    let (mut far, mut port) = (false, false);
+    for field in encoding.fields {
+        match *field {
+            Field::Bits(slot) => bits[slot as usize] = Some(slot),
+            Field::Implicit(slot, value) => implicit[slot as usize] = Some(value),
+            Field::Disp => has_disp = true,
+            Field::Addr => has_addr = true,
+            Field::RelJmp => rel_jmp = true,
+            Field::Data => has_data = true,
+            Field::DataIfW => data_if_w = true,
+            Field::Far => far = true,
+            Field::Port => port = true,
+            Field::Literal { .. } => {}
+        }
+    }
+    let has = |slot: Slot| bits[slot as usize].is_some() || implicit[slot as usize].is_some();
+
+    if far != instruction.far {
+        return None;
+    }
+    // Encodings that imply w settle the operand size themselves, so `call [bx]` needs
+    // no `word` in front of the address.
+    let wide = match implicit[Slot::W as usize] {
+        Some(w) => w == 1,
+        None if bits[Slot::W as usize].is_none() && instruction.wide => return None,
+        None => instruction.wide,
+    };
+    let intersegment = has_addr && has_data;
+
+    let operands: Vec<Operand> = instruction.operands.iter().flatten().copied().collect();
+    let d_choices: &[u8] = match (bits[Slot::D as usize], implicit[Slot::D as usize]) {
+        (Some(_), _) => &[0, 1],
+        (None, Some(1)) => &[1],
+        _ => &[0],
+    };
+
+    let has_reg = has(Slot::Reg) || has(Slot::Sr);
+    'd: for &d in d_choices {
+        // A port takes the place of the rm operand.
+        let has_rm = port || has(Slot::Mod) || (has_addr && !rel_jmp && !intersegment);
+        let rm_role = if port { Role::Port } else { Role::Rm };
+        let mut roles = Vec::new();
+        let ordered = if d == 1 {
+            [(has_reg, Role::Reg), (has_rm, rm_role)]
+        } else {
+            [(has_rm, rm_role), (has_reg, Role::Reg)]
+        };
+        roles.extend(
+            ordered
+                .iter()
+                .filter(|(present, _)| *present)
+                .map(|(_, role)| *role),
+        );
+        if rel_jmp {
+            roles.push(Role::RelJmp);
+        } else if intersegment {
+            roles.push(Role::Intersegment);
+        } else if has(Slot::V) {
+            roles.push(Role::Count);
+        } else if has_data && !port {
+            roles.push(Role::Data);
+        }
+        if roles.len() != operands.len() {
+            continue;
+        }
+
+        let mut slots = [0u8; SLOT_COUNT];
+        for (slot, value) in implicit.iter().enumerate() {
+            slots[slot] = value.unwrap_or_default();
+        }
+        slots[Slot::D as usize] = d;
+        slots[Slot::W as usize] = wide as u8;
+        let mut disp: Vec<u8> = Vec::new();
+        let mut data: Vec<u8> = Vec::new();
+
+        for (role, operand) in roles.iter().zip(&operands) {
+            match (role, operand) {
+                (Role::Reg, Operand::Register(reg)) if has(Slot::Sr) => {
+                    if !reg.is_segment() {
+                        continue 'd;
+                    }
+                    slots[Slot::Sr as usize] = reg.index();
+                }
+                (Role::Reg, Operand::Register(reg)) => {
+                    if reg.is_wide() != wide || reg.is_segment() {
+                        continue 'd;
+                    }
+                    match implicit[Slot::Reg as usize] {
+                        Some(index) if index != reg.index() => continue 'd,
+                        _ => slots[Slot::Reg as usize] = reg.index(),
+                    }
+                }
+                (Role::Reg, Operand::Immediate(opcode)) if has(Slot::Esc) => {
+                    let Ok(opcode @ 0..=0b111_111) = u8::try_from(*opcode) else {
+                        continue 'd;
+                    };
+                    slots[Slot::Esc as usize] = opcode >> 3;
+                    slots[Slot::Reg as usize] = opcode & 0b111;
+                }
+                (Role::Rm, Operand::Register(reg)) if has(Slot::Mod) => {
+                    if reg.is_wide() != wide || reg.is_segment() {
+                        continue 'd;
+                    }
+                    // The short xchg implies ax.
+                    if implicit[Slot::Rm as usize].is_some_and(|rm| rm != reg.index()) {
+                        continue 'd;
+                    }
+                    slots[Slot::Mod as usize] = 0b11;
+                    slots[Slot::Rm as usize] = reg.index();
+                }
+                (
+                    Role::Rm,
+                    Operand::Memory {
+                        base,
+                        index,
+                        disp: offset,
+                        ..
+                    },
+                ) => {
+                    // A mod fixed by the encoding, like the short xchg, only means registers.
+                    if implicit[Slot::Mod as usize].is_some() {
+                        continue 'd;
+                    }
+                    if has(Slot::Mod) {
+                        let Some((r#mod, rm, bytes)) = encode_address(*base, *index, *offset)
+                        else {
+                            continue 'd;
+                        };
+                        slots[Slot::Mod as usize] = r#mod;
+                        slots[Slot::Rm as usize] = rm;
+                        disp = bytes;
+                    } else if base.is_none() && index.is_none() {
+                        disp = offset.to_le_bytes().to_vec();
+                    } else {
+                        continue 'd;
+                    }
+                }
+                (Role::Data, Operand::Immediate(value)) => {
+                    let value = *value;
+                    let fits = if wide {
+                        (i16::MIN as i32..=u16::MAX as i32).contains(&value)
+                    } else {
+                        (i8::MIN as i32..=u8::MAX as i32).contains(&value)
+                    };
+                    if !fits {
+                        continue 'd;
+                    }
+                    // Sign extending a byte is shorter whenever the value allows it.
+                    let sign_extend = bits[Slot::S as usize].is_some()
+                        && wide
+                        && (i8::MIN as i16..=i8::MAX as i16).contains(&(value as i16));
+                    slots[Slot::S as usize] = sign_extend as u8;
+                    data = if data_if_w && wide && !sign_extend {
+                        (value as u16).to_le_bytes().to_vec()
+                    } else {
+                        vec![value as u8]
+                    };
+                }
+                (Role::RelJmp, Operand::RelativeJump(offset)) => {
+                    if has_addr {
+                        disp = offset.to_le_bytes().to_vec();
+                    } else if has_disp && i8::try_from(*offset).is_ok() {
+                        disp = vec![*offset as u8];
+                    } else {
+                        continue 'd;
+                    }
+                }
+                (Role::Port, Operand::Immediate(number)) if has_data => {
+                    let Ok(number) = u8::try_from(*number) else {
+                        continue 'd;
+                    };
+                    data = vec![number];
+                }
+                (Role::Port, Operand::Register(Register::DX)) if !has_data => {}
+                (Role::Count, Operand::Register(Register::CL)) => slots[Slot::V as usize] = 1,
+                (Role::Count, Operand::Immediate(1)) => slots[Slot::V as usize] = 0,
+                (Role::Intersegment, Operand::Intersegment { segment, offset }) => {
+                    disp = offset.to_le_bytes().to_vec();
+                    data = segment.to_le_bytes().to_vec();
+                }
+                _ => continue 'd,
+            }
+        }
+
+        // Prefixes go in front: `11110000` to lock, `1111001 z` to repeat, `001 sr 110`
+        // to override the segment.
+        let lock = instruction.lock.then_some(0xf0);
+        let repeat = instruction.repeat.map(|repeat| match repeat {
+            Repeat::Rep => 0xf3,
+            Repeat::Repne => 0xf2,
+        });
+        let segment = operands
+            .iter()
+            .filter_map(|operand| match operand {
+                Operand::Memory { segment, .. } => *segment,
+                _ => None,
+            })
+            .chain(instruction.segment)
+            .map(|reg| 0b0010_0110 | (reg.index() << 3));
+        let mut bytes: Vec<u8> = lock.into_iter().chain(repeat).chain(segment).collect();
+        bytes.extend(emit_bits(encoding, &slots));
+        bytes.extend(disp);
+        bytes.extend(data);
+        return Some(bytes);
+    }
+    None
+}
+
+/// Picks the mod and rm fields for a memory operand along with its displacement bytes.
+fn encode_address(
+    base: Option<Register>,
+    index: Option<Register>,
+    disp: i16,
+) -> Option<(u8, u8, Vec<u8>)> {
+    use Register::*;
+    let rm = match (base, index) {
+        (None, None) => return Some((0b00, 0b110, disp.to_le_bytes().to_vec())),
+        (Some(BX), Some(SI)) => 0b000,
+        (Some(BX), Some(DI)) => 0b001,
+        (Some(BP), Some(SI)) => 0b010,
+        (Some(BP), Some(DI)) => 0b011,
+        (Some(SI), None) => 0b100,
+        (Some(DI), None) => 0b101,
+        (Some(BP), None) => 0b110,
+        (Some(BX), None) => 0b111,
+        _ => return None,
+    };
+    // `[bp]` has no mod 00 form; that slot is the direct address.
+    if disp == 0 && rm != 0b110 {
+        Some((0b00, rm, Vec::new()))
+    } else if let Ok(byte) = i8::try_from(disp) {
+        Some((0b01, rm, vec![byte as u8]))
+    } else {
+        Some((0b10, rm, disp.to_le_bytes().to_vec()))
+    }
+}
+
+/// Packs the literal and captured bit fields of `encoding`, most significant bit first.
+fn emit_bits(encoding: &Encoding, slots: &[u8; SLOT_COUNT]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut current = 0u8;
+    let mut used = 0;
+    for field in encoding.fields {
+        let (value, count) = match *field {
+            Field::Literal { value, count } => (value, count),
+            Field::Bits(slot) => (slots[slot as usize], slot.width()),
+            _ => continue,
+        };
+        current = ((current as u16) << count) as u8 | (value & (0xff >> (8 - count)));
+        used += count;
+        if used == 8 {
+            bytes.push(current);
+            current = 0;
+            used = 0;
+        }
+    }
+    bytes
+}
@@ -0,0 +1,324 @@
+//! Decodes a single instruction by matching the input against the encodings in
+//! [`crate::table`], reading only the bytes the matching encoding asks for.
+
+use crate::{
+    instruction::{Instruction, ParseErrorKind, ParseInstructionError, Repeat},
+    mode::Mode,
+    opcode::Opcode,
+    operand::Operand,
+    register::Register,
+    table::{self, Encoding, Field, Slot, SLOT_COUNT},
+    MAX_PREFIXES,
+};
+
+/// The input ended before the encoding being matched was complete.
+struct Truncated;
+
+/// Hands out bit fields most significant bit first, pulling in the next byte only
+/// once the current one has been used up.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    current: u8,
+    remaining: u8,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            pos: 0,
+            current: 0,
+            remaining: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u8) -> Result<u8, Truncated> {
+        if self.remaining == 0 {
+            self.current = self.byte()?;
+            self.remaining = 8;
+        }
+        debug_assert!(count <= self.remaining, "fields never straddle a byte");
+        self.remaining -= count;
+        Ok((self.current >> self.remaining) & (0xff >> (8 - count)))
+    }
+
+    fn byte(&mut self) -> Result<u8, Truncated> {
+        let byte = *self.bytes.get(self.pos).ok_or(Truncated)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn word(&mut self) -> Result<u16, Truncated> {
+        let lo = self.byte()?;
+        let hi = self.byte()?;
+        Ok(u16::from_le_bytes([lo, hi]))
+    }
+}
+
+/// Everything captured while matching an encoding.
+#[derive(Default)]
+struct Fields {
+    slots: [u8; SLOT_COUNT],
+    has: [bool; SLOT_COUNT],
+    disp: Option<u16>,
+    data: Option<i32>,
+    has_addr: bool,
+    rel_jmp: bool,
+    far: bool,
+    port: bool,
+    size: u8,
+}
+
+impl Fields {
+    fn get(&self, slot: Slot) -> u8 {
+        self.slots[slot as usize]
+    }
+
+    fn is_set(&self, slot: Slot) -> bool {
+        self.get(slot) != 0
+    }
+
+    fn has(&self, slot: Slot) -> bool {
+        self.has[slot as usize]
+    }
+}
+
+/// The segment register a `001 sr 110` override prefix selects.
+fn segment_prefix(byte: u8) -> Option<Register> {
+    (byte & 0b1110_0111 == 0b0010_0110).then(|| Register::from_segment_bits(byte >> 3))
+}
+
+/// The repeat prefix `1111001 z` selects.
+fn repeat_prefix(byte: u8) -> Option<Repeat> {
+    match byte {
+        0xf3 => Some(Repeat::Rep),
+        0xf2 => Some(Repeat::Repne),
+        _ => None,
+    }
+}
+
+/// The lock prefix.
+const LOCK: u8 = 0xf0;
+
+/// Decodes one instruction along with any prefixes in front of it. When a prefix
+/// appears more than once, the last one wins.
+pub fn decode(bytes: &[u8]) -> Result<Instruction, ParseInstructionError> {
+    let (mut segment, mut repeat, mut lock) = (None, None, false);
+    let mut skipped = 0;
+    while let Some(&byte) = bytes.get(skipped) {
+        if byte == LOCK {
+            lock = true;
+        } else if let Some(prefix) = segment_prefix(byte) {
+            segment = Some(prefix);
+        } else if let Some(prefix) = repeat_prefix(byte) {
+            repeat = Some(prefix);
+        } else {
+            break;
+        }
+        skipped += 1;
+        // Stop scanning as soon as the run is too long, so a caller stepping through
+        // a long run a byte at a time doesn't rescan all of it each time.
+        if skipped > MAX_PREFIXES {
+            return Err(ParseInstructionError::new(
+                ParseErrorKind::TooLong,
+                "There are too many prefixes in front of this instruction.",
+            ));
+        }
+    }
+
+    let mut instruction = decode_unprefixed(&bytes[skipped..])?;
+    instruction.size += skipped as u8;
+    instruction.repeat = repeat;
+    instruction.lock = lock;
+    // nasm only takes lock on an xchg whose memory operand comes first.
+    if lock && instruction.opcode == Opcode::Xchg && instruction.operands().any(Operand::is_memory)
+    {
+        instruction.operands.swap(0, 1);
+    }
+    if instruction.operands().any(Operand::is_memory) {
+        for operand in instruction.operands.iter_mut().flatten() {
+            if let Operand::Memory { segment: slot, .. } = operand {
+                *slot = segment;
+            }
+        }
+    } else {
+        instruction.segment = segment;
+    }
+    Ok(instruction)
+}
+
+fn decode_unprefixed(bytes: &[u8]) -> Result<Instruction, ParseInstructionError> {
+    let Some(&first) = bytes.first() else {
+        return Err(ParseInstructionError::new(
+            ParseErrorKind::Truncated,
+            "There are no bytes left to decode.",
+        ));
+    };
+
+    let mut truncated = false;
+    for &index in table::candidates(first) {
+        let encoding = &table::ENCODINGS[index as usize];
+        match try_match(encoding, bytes) {
+            Ok(Some(fields)) => return Ok(build(encoding.opcode, &fields)),
+            Ok(None) => {}
+            Err(Truncated) => truncated = true,
+        }
+    }
+
+    if truncated {
+        Err(ParseInstructionError::new(
+            ParseErrorKind::Truncated,
+            "The input ended in the middle of an instruction.",
+        ))
+    } else {
+        Err(ParseInstructionError::new(
+            ParseErrorKind::UnknownOpcode,
+            "This opcode is unimplemented.",
+        ))
+    }
+}
+
+/// Returns `Ok(None)` when the literal bits of `encoding` don't match the input.
+fn try_match(encoding: &Encoding, bytes: &[u8]) -> Result<Option<Fields>, Truncated> {
+    let mut reader = Reader::new(bytes);
+    let mut fields = Fields::default();
+    let (mut has_disp, mut has_data, mut data_if_w) = (false, false, false);
+
+    for field in encoding.fields {
+        match *field {
+            Field::Literal { value, count } => {
+                if reader.bits(count)? != value {
+                    return Ok(None);
+                }
+            }
+            Field::Bits(slot) => {
+                fields.slots[slot as usize] = reader.bits(slot.width())?;
+                fields.has[slot as usize] = true;
+            }
+            Field::Implicit(slot, value) => {
+                fields.slots[slot as usize] = value;
+                fields.has[slot as usize] = true;
+            }
+            Field::Disp => has_disp = true,
+            Field::Addr => fields.has_addr = true,
+            Field::RelJmp => fields.rel_jmp = true,
+            Field::Data => has_data = true,
+            Field::DataIfW => data_if_w = true,
+            Field::Far => fields.far = true,
+            Field::Port => fields.port = true,
+        }
+    }
+
+    let r#mod = fields
+        .has(Slot::Mod)
+        .then(|| Mode::from(fields.get(Slot::Mod)));
+    let direct_address = r#mod == Some(Mode::Memory) && fields.get(Slot::Rm) == 0b110;
+    if fields.has_addr || r#mod == Some(Mode::Displace16Bits) || direct_address {
+        fields.disp = Some(reader.word()?);
+    } else if has_disp || r#mod == Some(Mode::Displace8Bits) {
+        fields.disp = Some(reader.byte()? as i8 as u16);
+    }
+
+    if has_data {
+        let sign_extend = fields.is_set(Slot::S);
+        fields.data = Some(if data_if_w && fields.is_set(Slot::W) && !sign_extend {
+            reader.word()? as i32
+        } else if sign_extend {
+            reader.byte()? as i8 as i32
+        } else {
+            reader.byte()? as i32
+        });
+    }
+
+    fields.size = reader.pos as u8;
+    Ok(Some(fields))
+}
+
+/// Turns the captured fields into operands, destination first.
+fn build(opcode: Opcode, fields: &Fields) -> Instruction {
+    let wide = fields.is_set(Slot::W);
+    let disp = fields.disp.unwrap_or_default() as i16;
+    // A direct far jump or call spells out the offset and then the segment.
+    let intersegment = fields.has_addr && fields.data.is_some();
+
+    let reg = if fields.has(Slot::Sr) {
+        Some(Operand::Register(Register::from_segment_bits(
+            fields.get(Slot::Sr),
+        )))
+    } else if fields.has(Slot::Esc) {
+        // The coprocessor opcode, split across the esc and reg fields.
+        let opcode = (fields.get(Slot::Esc) << 3) | fields.get(Slot::Reg);
+        Some(Operand::Immediate(opcode as i32))
+    } else {
+        fields
+            .has(Slot::Reg)
+            .then(|| Operand::Register(Register::from_bits(fields.get(Slot::Reg), wide)))
+    };
+    let port = fields
+        .data
+        .map_or(Operand::Register(Register::DX), Operand::Immediate);
+    let rm = if fields.port {
+        Some(port)
+    } else if fields.has(Slot::Mod) {
+        let r#mod = Mode::from(fields.get(Slot::Mod));
+        let rm = fields.get(Slot::Rm);
+        Some(match r#mod {
+            Mode::Register => Operand::Register(Register::from_bits(rm, wide)),
+            _ => Operand::effective_address(rm, r#mod == Mode::Memory && rm == 0b110, disp),
+        })
+    } else if fields.has_addr && !fields.rel_jmp && !intersegment {
+        Some(Operand::Memory {
+            base: None,
+            index: None,
+            disp,
+            segment: None,
+        })
+    } else {
+        None
+    };
+
+    let (first, second) = if fields.is_set(Slot::D) {
+        (reg, rm)
+    } else {
+        (rm, reg)
+    };
+    let extra = if fields.port {
+        None
+    } else if fields.rel_jmp {
+        Some(Operand::RelativeJump(disp))
+    } else if intersegment {
+        fields.data.map(|segment| Operand::Intersegment {
+            segment: segment as u16,
+            offset: disp as u16,
+        })
+    } else if fields.has(Slot::V) {
+        // Shifts move by cl when v is set and by one otherwise.
+        Some(if fields.is_set(Slot::V) {
+            Operand::Register(Register::CL)
+        } else {
+            Operand::Immediate(1)
+        })
+    } else {
+        fields.data.map(Operand::Immediate)
+    };
+
+    let mut operands = [None; 2];
+    for (slot, operand) in operands
+        .iter_mut()
+        .zip([first, second, extra].into_iter().flatten())
+    {
+        *slot = Some(operand);
+    }
+
+    Instruction {
+        opcode,
+        operands,
+        wide,
+        far: fields.far,
+        lock: false,
+        repeat: None,
+        segment: None,
+        size: fields.size,
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    fmt::Display,
+    ops::{BitAnd, BitOr, Not},
+};
+
+/// The flags register, laid out the way the 8086 stores it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Flags(u16);
+
+impl Flags {
+    pub const CARRY: Self = Self(1 << 0);
+    pub const PARITY: Self = Self(1 << 2);
+    pub const AUX_CARRY: Self = Self(1 << 4);
+    pub const ZERO: Self = Self(1 << 6);
+    pub const SIGN: Self = Self(1 << 7);
+    pub const TRAP: Self = Self(1 << 8);
+    pub const INTERRUPT: Self = Self(1 << 9);
+    pub const DIRECTION: Self = Self(1 << 10);
+    pub const OVERFLOW: Self = Self(1 << 11);
+
+    /// Every flag the arithmetic instructions define.
+    pub const ARITHMETIC: Self = Self(
+        Self::CARRY.0
+            | Self::PARITY.0
+            | Self::AUX_CARRY.0
+            | Self::ZERO.0
+            | Self::SIGN.0
+            | Self::OVERFLOW.0,
+    );
+
+    /// The flags in the low byte of the register, which lahf and sahf move to and
+    /// from ah: all the arithmetic ones but OF.
+    pub const LOW_BYTE: Self =
+        Self(Self::CARRY.0 | Self::PARITY.0 | Self::AUX_CARRY.0 | Self::ZERO.0 | Self::SIGN.0);
+
+    /// Every flag the 8086 has; the remaining bits of the register are unused.
+    pub const ALL: Self =
+        Self(Self::ARITHMETIC.0 | Self::TRAP.0 | Self::INTERRUPT.0 | Self::DIRECTION.0);
+
+    /// Trace order, paired with the letter each flag prints as.
+    const LETTERS: [(Self, char); 9] = [
+        (Self::CARRY, 'C'),
+        (Self::PARITY, 'P'),
+        (Self::AUX_CARRY, 'A'),
+        (Self::ZERO, 'Z'),
+        (Self::SIGN, 'S'),
+        (Self::TRAP, 'T'),
+        (Self::INTERRUPT, 'I'),
+        (Self::DIRECTION, 'D'),
+        (Self::OVERFLOW, 'O'),
+    ];
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn bits(self) -> u16 {
+        self.0
+    }
+
+    pub const fn from_bits(bits: u16) -> Self {
+        Self(bits)
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn set(&mut self, other: Self, value: bool) {
+        if value {
+            self.0 |= other.0;
+        } else {
+            self.0 &= !other.0;
+        }
+    }
+
+    /// Replaces the flags selected by `mask` with their values in `other`.
+    pub fn update(&mut self, mask: Self, other: Self) {
+        self.0 = (self.0 & !mask.0) | (other.0 & mask.0);
+    }
+}
+
+impl BitOr for Flags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for Flags {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl Not for Flags {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(!self.0)
+    }
+}
+
+impl Display for Flags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (flag, letter) in Self::LETTERS {
+            if self.contains(flag) {
+                write!(f, "{}", letter)?;
+            }
+        }
+        Ok(())
+    }
+}
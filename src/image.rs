@@ -0,0 +1,149 @@
+//! Renders a region of simulated memory as an image, the way the draw_rectangle
+//! listings expect it to be viewed: four bytes per pixel, red, green, blue, alpha.
+//!
+//! Both formats are written by hand. PNG pixel data goes into stored (uncompressed)
+//! deflate blocks, which keeps the output byte for byte reproducible for golden tests.
+
+use std::fmt::Display;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Binary `P6` portable pixmap. It has no alpha channel, so alpha is dropped.
+    Ppm,
+    /// Eight bit RGBA PNG.
+    Png,
+}
+
+impl ImageFormat {
+    /// Picks the format from a file extension, e.g. `"png"`.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "ppm" => Some(Self::Ppm),
+            "png" => Some(Self::Png),
+            _ => None,
+        }
+    }
+}
+
+/// Why a region of memory can't be exported as an image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageError {
+    /// The width or the height is zero.
+    Empty,
+    /// The pixels would run past the end of the 1 MiB address space.
+    OutOfBounds {
+        address: u32,
+        width: usize,
+        height: usize,
+    },
+}
+
+impl Display for ImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageError::Empty => write!(f, "the width and height have to be at least 1"),
+            ImageError::OutOfBounds {
+                address,
+                width,
+                height,
+            } => write!(
+                f,
+                "a {}x{} image at address {} doesn't fit in memory",
+                width, height, address
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+/// Encodes `pixels`, `width * height` RGBA quadruples in rows from the top.
+///
+/// Panics if either dimension is zero or `pixels` doesn't hold exactly that many bytes.
+pub fn encode(format: ImageFormat, pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    assert!(width > 0 && height > 0, "images can't be empty");
+    assert_eq!(pixels.len(), width * height * 4, "pixels must be RGBA");
+    match format {
+        ImageFormat::Ppm => ppm(pixels, width, height),
+        ImageFormat::Png => png(pixels, width, height),
+    }
+}
+
+fn ppm(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut out = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    for pixel in pixels.chunks_exact(4) {
+        out.extend_from_slice(&pixel[..3]);
+    }
+    out
+}
+
+fn png(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+
+    let mut header = Vec::new();
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    // Bit depth 8, color type 6 (RGBA), default compression, filter and interlacing.
+    header.extend([8, 6, 0, 0, 0]);
+    chunk(&mut out, b"IHDR", &header);
+
+    // Every scanline starts with its filter type, and 0 means unfiltered.
+    let mut raw = Vec::with_capacity(pixels.len() + height);
+    for row in pixels.chunks_exact(width * 4) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend((data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend(crc.to_be_bytes());
+}
+
+/// A zlib stream holding `data` in uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = u16::MAX as usize;
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    while let Some(block) = blocks.next() {
+        // The low bit marks the final block; the block type bits stay 00 for stored.
+        out.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
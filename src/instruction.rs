@@ -1,392 +1,231 @@
-use bitvec::{slice::BitSlice, prelude::*};
+use std::fmt::Display;
 
-use crate::{mode::Mode, register::Register};
+use crate::{decode, opcode::Opcode, operand::Operand, register::Register};
 
-#[derive(Debug)]
-pub enum Instruction {
-    RegisterMemoryMov {
-        // true  = destination in reg
-        // false = destination in rm
-        d: bool,
-        wide: bool,
-        r#mod: Mode,
-        reg: Register,
-        rm: [bool; 3],
-        disp: Option<u16>,
-        bytes_used: u8,
-    },
-    ImmediateRegisterMov {
-        wide: bool,
-        reg: Register,
-        data: u16,
-        bytes_used: u8,
-    },
-    ImmediateRegisterMemoryMov {
-        wide: bool,
-        r#mod: Mode,
-        rm: [bool; 3],
-        disp: Option<u16>,
-        data: u16,
-        bytes_used: u8,
-    },
-    MemoryAccumMov {
-        to_memory: bool,
-        wide: bool,
-        addr: u16,
-        bytes_used: u8,
-    },
+/// The prefixes that repeat a string instruction until cx runs out.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Repeat {
+    /// `f3`, also spelled repe and repz. cmps and scas stop early once ZF is clear.
+    Rep,
+    /// `f2`, also spelled repnz. cmps and scas stop early once ZF is set.
+    Repne,
 }
 
-fn deserialize_effective_address(rm: &[bool; 3], r#mod: Mode, disp: Option<u16>) -> String {
-    match rm {
-        [false, false, false] => "bx + si".to_string(),
-        [false, false, true] => "bx + di".to_string(),
-        [false, true, false] => "bp + si".to_string(),
-        [false, true, true] => "bp + di".to_string(),
-        [true, false, false] => "si".to_string(),
-        [true, false, true] => "di".to_string(),
-        [true, true, false] => {
-            println!("Edge case!");
-            if r#mod == Mode::Memory {
-                return format!("[{}]", disp.unwrap());
-            }
-            "bp".to_string()
+impl Repeat {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rep => "rep",
+            Self::Repne => "repne",
         }
-        [true, true, true] => "bx".to_string(),
     }
 }
 
-fn deserialize_displacement(r#mod: Mode, disp: Option<u16>, wide: bool) -> String {
-    if r#mod == Mode::Memory {
-        return String::from("");
-    }
-    let val = disp.unwrap();
-    if val == 0 {
-        return String::from("");
-    }
-    if wide {
-        let signed_val = val as i32;
-        let op = if signed_val < 0 { "-" } else { "+" };
-        format!(" {} {}", op, signed_val.abs())
-    } else {
-        let signed_val = val as i16;
-        let op = if signed_val < 0 { "-" } else { "+" };
-        format!(" {} {}", op, signed_val.abs())
-    }
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    /// Destination first. Unused operands are `None`.
+    pub operands: [Option<Operand>; 2],
+    pub wide: bool,
+    /// The instruction transfers control to another segment, e.g. `call far [bx]`.
+    pub far: bool,
+    /// Prefixed with lock, which holds the bus for the rest of the instruction.
+    pub lock: bool,
+    pub repeat: Option<Repeat>,
+    /// A segment override none of the operands took, like the one on `es movsb`,
+    /// which applies to the implicit `[si]`.
+    pub segment: Option<Register>,
+    /// The number of bytes the instruction was encoded in.
+    pub size: u8,
 }
 
 impl Instruction {
     pub fn bytes(&self) -> u8 {
-        match self {
-            Instruction::RegisterMemoryMov { bytes_used, .. } => *bytes_used,
-            Instruction::ImmediateRegisterMov { bytes_used, .. } => *bytes_used,
-            Instruction::ImmediateRegisterMemoryMov { bytes_used, .. } => *bytes_used,
-            Instruction::MemoryAccumMov { bytes_used, .. } => *bytes_used,
-        }
+        self.size
     }
 
     pub fn opcode_name(&self) -> &str {
-        match self {
-            Instruction::RegisterMemoryMov { .. } => "mov",
-            Instruction::ImmediateRegisterMov { .. } => "mov",
-            Instruction::ImmediateRegisterMemoryMov { .. } => "mov",
-            Instruction::MemoryAccumMov { .. } => "mov",
-        }
+        self.opcode.name()
     }
 
-    pub fn to_asm(&self) -> String {
-        match self {
-            Instruction::RegisterMemoryMov {
-                d,
-                wide,
-                r#mod,
-                reg,
-                rm,
-                disp,
-                bytes_used,
-            } => {
-                let rm_reg = if *r#mod == Mode::Register {
-                    Register::from_bits(rm, *wide).to_string()
-                } else {
-                    let effective_address = deserialize_effective_address(rm, *r#mod, *disp);
-                    let disp_str = deserialize_displacement(*r#mod, *disp, *wide);
-
-                    if effective_address.starts_with('[') {
-                        effective_address
-                    } else {
-                        format!("[{}{}]", effective_address, disp_str)
-                    }
-                };
-                let (src, dest) = if *d {
-                    (rm_reg, reg.to_string())
-                } else {
-                    (reg.to_string(), rm_reg)
-                };
-                format!("{} {}, {}", self.opcode_name(), dest, src)
-            }
+    /// The opcode name along with any prefixes, and the `b` or `w` suffix nasm wants
+    /// on string instructions, e.g. `rep movsb`.
+    pub fn mnemonic(&self) -> String {
+        let mut words: Vec<String> = self
+            .lock
+            .then(|| "lock".to_string())
+            .into_iter()
+            .chain(self.repeat.map(|repeat| repeat.name().to_string()))
+            .chain(self.segment.map(|reg| reg.to_string()))
+            .collect();
+        let suffix = match (self.opcode.is_string(), self.wide) {
+            (false, _) => "",
+            (true, true) => "w",
+            (true, false) => "b",
+        };
+        words.push(format!("{}{}", self.opcode_name(), suffix));
+        words.join(" ")
+    }
 
-            Instruction::ImmediateRegisterMov {
-                reg, data, wide, ..
-            } => {
-                if *wide {
-                    format!("{} {}, {}", self.opcode_name(), reg, *data as i16)
-                } else {
-                    format!("{} {}, {}", self.opcode_name(), reg, *data as i8)
-                }
-            }
-            Instruction::ImmediateRegisterMemoryMov {
-                wide,
-                r#mod,
-                rm,
-                disp,
-                data,
-                ..
-            } => {
-                let dest = {
-                    let effective_address = deserialize_effective_address(rm, *r#mod, *disp);
-                    let disp_str = deserialize_displacement(*r#mod, *disp, *wide);
+    pub fn operands(&self) -> impl Iterator<Item = &Operand> {
+        self.operands.iter().flatten()
+    }
 
-                    if effective_address.starts_with('[') {
-                        effective_address
-                    } else {
-                        format!("[{}{}]", effective_address, disp_str)
-                    }
+    pub fn to_asm(&self) -> String {
+        // Without a register operand nasm can't infer the operand size, so it has to
+        // be spelled out on the immediate, or on the memory operand if there is none.
+        // A shift count says nothing about the size of what is shifted, and what esc
+        // hands to a coprocessor has no size at all.
+        let sizing = match self.opcode {
+            Opcode::Esc => 0,
+            op if op.is_shift() => 1,
+            _ => 2,
+        };
+        let sizing_operands = || self.operands().take(sizing);
+        let needs_size = !sizing_operands().any(Operand::is_register)
+            && sizing_operands().any(Operand::is_memory);
+        let size_on_immediate =
+            sizing_operands().any(|operand| matches!(operand, Operand::Immediate(_)));
+
+        let operands: Vec<String> = self
+            .operands()
+            .enumerate()
+            .map(|(index, operand)| {
+                let text = self.deserialize_operand(operand);
+                let sized = match operand {
+                    _ if index >= sizing => false,
+                    Operand::Immediate(_) => size_on_immediate,
+                    Operand::Memory { .. } => !size_on_immediate,
+                    _ => false,
                 };
-                let src = if *wide {
-                    format!("word {}", data)
+                if self.far && operand.is_memory() {
+                    // A far pointer is always a segment and an offset, so nasm needs
+                    // no size on top of `far`.
+                    format!("far {}", text)
+                } else if needs_size && sized {
+                    let size = if self.wide { "word" } else { "byte" };
+                    format!("{} {}", size, text)
                 } else {
-                    format!("byte {}", data)
-                };
-
-                format!("{} {}, {}", self.opcode_name(), dest, src)
-            }
-
-            Instruction::MemoryAccumMov {
-                to_memory,
-                wide,
-                addr,
-                bytes_used,
-            } => {
-                    let (src, dest) = if *to_memory {
-                        ("ax".to_string(), format!("[{}]", addr))
-                    } else {
-                        (format!("[{}]", addr), "ax".to_string())
-                    };
-
-                    format!("{} {}, {}", self.opcode_name(), dest, src)
+                    text
                 }
+            })
+            .collect();
+
+        if operands.is_empty() {
+            self.mnemonic()
+        } else {
+            format!("{} {}", self.mnemonic(), operands.join(", "))
         }
     }
 
-    fn try_parse_register_memory_mov(
-        bits: &BitSlice<u8, Msb0>,
-    ) -> Result<Self, ParseInstructionError> {
-        if bits.len() < 16 {
-            return Err(ParseInstructionError::new(
-                "Incoming bits has less than 16 bits!",
-            ));
-        };
-        println!("{}", bits);
-        let d = bits[6];
-        let wide = bits[7];
-        let r#mod = Mode::from(&[bits[8], bits[9]]);
-        let reg = Register::from_bits(&[bits[10], bits[11], bits[12]], wide);
-        let rm = [bits[13], bits[14], bits[15]];
-        let mut disp = None;
-        let mut bytes_used = 2;
-
-        match r#mod {
-            Mode::Displace8Bits => {
-                if bits.len() < 24 {
-                    return Err(ParseInstructionError::new(
-                        "Incoming instruction has an 8 bit displacement, but the `disp_lo` byte wasn't provided. Requires at least 24 bits.",
-                    ));
-                }
-                disp = Some(bits[16..24].load::<u8>() as u16);
-                bytes_used = 3;
-            }
-            Mode::Displace16Bits => {
-                if bits.len() < 32 {
-                    return Err(ParseInstructionError::new(
-                        "Incoming instruction has an 16 bit displacement, but the `disp_hi` byte wasn't provided. Requires at least 32 bits.",
-                    ));
+    fn deserialize_operand(&self, operand: &Operand) -> String {
+        match *operand {
+            Operand::Register(reg) => reg.to_string(),
+            Operand::Memory {
+                base,
+                index,
+                disp,
+                segment,
+            } => {
+                let segment = segment.map(|reg| format!("{}:", reg)).unwrap_or_default();
+                let terms: Vec<String> = [base, index]
+                    .into_iter()
+                    .flatten()
+                    .map(|reg| reg.to_string())
+                    .collect();
+                if terms.is_empty() {
+                    return format!("{}[{}]", segment, disp as u16);
                 }
-                disp = Some(bits[16..32].load::<u16>());
-                bytes_used = 4;
+                let disp_str = match disp {
+                    0 => String::new(),
+                    d if d < 0 => format!(" - {}", (d as i32).abs()),
+                    d => format!(" + {}", d),
+                };
+                format!("{}[{}{}]", segment, terms.join(" + "), disp_str)
             }
-            Mode::Memory => {
-                if rm == [true, true, false] {
-                    if bits.len() < 32 {
-                        return Err(ParseInstructionError::new(
-                        "Incoming instruction has an 16 bit displacement, but the `disp_hi` byte wasn't provided. Requires at least 32 bits.",
-                    ));
-                    }
-                    disp = Some(bits[16..32].load::<u16>());
-                    bytes_used = 4;
-                }
+            // Port numbers and interrupt vectors are unsigned bytes.
+            Operand::Immediate(value)
+                if matches!(self.opcode, Opcode::In | Opcode::Out | Opcode::Int) =>
+            {
+                value.to_string()
             }
-            _ => {}
-        }
-
-        Ok(Self::RegisterMemoryMov {
-            d,
-            wide,
-            r#mod,
-            reg,
-            rm,
-            disp,
-            bytes_used,
-        })
-    }
-
-    fn try_parse_immediate_register_mov(
-        bits: &BitSlice<u8, Msb0>,
-    ) -> Result<Instruction, ParseInstructionError> {
-        if bits.len() < 16 {
-            return Err(ParseInstructionError::new(
-                "Incoming bits has less than 16 bits!",
-            ));
-        };
-        let wide = bits[4];
-        let reg = Register::from_bits(&[bits[5], bits[6], bits[7]], wide);
-        let mut bytes_used = 2;
-        let data = if wide {
-            if bits.len() < 24 {
-                return Err(ParseInstructionError::new(
-                    "Expected wide data. Received less than 24 bits.",
-                ));
-            };
-            bytes_used = 3;
-            bits[8..24].load::<u16>()
-        } else {
-            bits[8..16].load::<u8>() as u16
-        };
-
-        Ok(Self::ImmediateRegisterMov {
-            wide,
-            reg,
-            data,
-            bytes_used,
-        })
-    }
-
-    fn try_parse_immediate_register_memory_mov(
-        bits: &BitSlice<u8, Msb0>,
-    ) -> Result<Instruction, ParseInstructionError> {
-        let wide = bits[7];
-        let r#mod = Mode::from(&[bits[8], bits[9]]);
-        let rm = [bits[13], bits[14], bits[15]];
-        let (disp, data, bytes_used) = match r#mod {
-            Mode::Displace8Bits => (
-                Some(bits[16..24].load::<u8>() as u16),
-                if wide {
-                    bits[24..40].load::<u16>()
-                } else {
-                    bits[24..32].load::<u8>() as u16
-                },
-                if wide { 5 } else { 4 },
-            ),
-            Mode::Displace16Bits => (
-                Some(bits[16..32].load::<u16>()),
-                if wide {
-                    bits[32..48].load::<u16>()
+            Operand::Immediate(value) => {
+                if self.wide {
+                    format!("{}", value as i16)
                 } else {
-                    bits[32..40].load::<u8>() as u16
-                },
-                if wide { 6 } else { 5 },
-            ),
-            Mode::Memory => {
-                if rm == [true, true, false] {
-                    (
-                        Some(bits[16..32].load::<u16>()),
-                        if wide {
-                            bits[32..48].load::<u16>()
-                        } else {
-                            bits[32..40].load::<u8>() as u16
-                        },
-                        if wide { 6 } else { 5 },
-                    )
-                } else {
-                    (
-                        None,
-                        if wide {
-                            bits[16..32].load::<u16>()
-                        } else {
-                            bits[16..24].load::<u8>() as u16
-                        },
-                        if wide { 4 } else { 3 },
-                    )
+                    format!("{}", value as i8)
                 }
             }
-
-            Mode::Register => (
-                None,
-                if wide {
-                    bits[16..32].load::<u16>()
-                } else {
-                    bits[16..24].load::<u8>() as u16
-                },
-                if wide { 4 } else { 3 },
-            ),
-        };
-        Ok(Self::ImmediateRegisterMemoryMov {
-            wide,
-            r#mod,
-            rm,
-            disp,
-            data,
-            bytes_used,
-        })
+            Operand::RelativeJump(disp) => {
+                // nasm's `$` is the start of the current instruction, while the
+                // displacement is relative to the end of it.
+                let offset = disp.wrapping_add(self.size as i16);
+                format!("${:+}", offset)
+            }
+            Operand::Intersegment { segment, offset } => format!("{}:{}", segment, offset),
+        }
     }
+}
 
-    fn try_parse_memory_accum_mov(
-        bits: &BitSlice<u8, Msb0>,
-    ) -> Result<Instruction, ParseInstructionError> {
-        let to_memory = bits[6];
-        let wide = bits[7];
-        let addr = if wide {
-            bits[8..24].load::<u16>()
-        } else {
-            bits[8..16].load::<u8>() as u16
-        };
-        let bytes_used = if wide { 3 } else { 2 };
-        Ok(Self::MemoryAccumMov {
-            to_memory,
-            wide,
-            addr,
-            bytes_used,
-        })
-    }
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// The leading bits don't match any encoding the decoder knows about.
+    UnknownOpcode,
+    /// The input ended before every field of the instruction could be read.
+    Truncated,
+    /// There are more than [`MAX_PREFIXES`](crate::MAX_PREFIXES) prefixes in front of the instruction.
+    TooLong,
 }
 
 #[derive(Debug)]
 pub struct ParseInstructionError {
+    pub kind: ParseErrorKind,
     pub msg: &'static str,
+    /// Byte offset of the offending instruction within the disassembled input.
+    pub offset: usize,
+    /// The bytes the decoder was looking at when it gave up.
+    pub bytes: Vec<u8>,
 }
 
 impl ParseInstructionError {
-    pub fn new(msg: &'static str) -> Self {
-        Self { msg }
+    pub fn new(kind: ParseErrorKind, msg: &'static str) -> Self {
+        Self {
+            kind,
+            msg,
+            offset: 0,
+            bytes: Vec::new(),
+        }
+    }
+
+    /// Records where in the input the error happened. The decoder only sees the
+    /// bytes from the start of the instruction, so the caller driving it fills this in.
+    pub fn at(mut self, offset: usize, bytes: &[u8]) -> Self {
+        self.offset = offset;
+        self.bytes = bytes.to_vec();
+        self
+    }
+}
+
+impl Display for ParseInstructionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} at offset {:#x}: {}",
+            self.kind, self.offset, self.msg
+        )?;
+        if !self.bytes.is_empty() {
+            let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            write!(f, " (bytes: {})", bytes.join(" "))?;
+        }
+        Ok(())
     }
 }
 
-impl<'a> TryFrom<&'a BitSlice<u8, Msb0>> for Instruction {
+impl std::error::Error for ParseInstructionError {}
+
+impl TryFrom<&[u8]> for Instruction {
     type Error = ParseInstructionError;
 
-    fn try_from(bits: &BitSlice<u8, Msb0>) -> Result<Self, Self::Error> {
-        match (
-            bits[0], bits[1], bits[2], bits[3], bits[4], bits[5], bits[6],
-        ) {
-            (true, false, false, false, true, false, _) => {
-                Self::try_parse_register_memory_mov(bits)
-            }
-            (true, false, true, true, _, _, _) => Self::try_parse_immediate_register_mov(bits),
-            // NOTE: we may need 7 bits for this one
-            (true, true, false, false, false, true, true) => {
-                Self::try_parse_immediate_register_memory_mov(bits)
-            }
-            (true, false, true, false, false, false, _) => Self::try_parse_memory_accum_mov(bits),
-            _ => unimplemented!("This opcode is unimplemented: {:?}", bits),
-        }
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        decode::decode(bytes)
     }
 }
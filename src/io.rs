@@ -0,0 +1,94 @@
+//! The 8086's separate 64 KiB I/O space, reached with in and out. Peripherals written
+//! in Rust implement [`PortDevice`] and are attached to the ports they answer.
+
+use std::{cell::RefCell, fmt::Debug, ops::RangeInclusive, rc::Rc};
+
+/// A peripheral on the I/O bus. A word access is split into two byte accesses, the
+/// low byte at `port` and the high byte at `port + 1`, as an 8088 performs them.
+pub trait PortDevice: Debug {
+    fn read(&mut self, port: u16) -> u8;
+
+    fn write(&mut self, port: u16, value: u8);
+
+    /// Called before every instruction. A device that wants attention, like a timer
+    /// whose count ran out, returns the interrupt vector it requests.
+    fn poll(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+/// A device shared with whoever attached it, so they can still look at its state.
+pub type SharedDevice = Rc<RefCell<dyn PortDevice>>;
+
+/// The devices attached to the I/O space. Ports nothing answers read as `0xff`, like
+/// a floating bus, and ignore writes.
+#[derive(Clone, Debug, Default)]
+pub struct Ports {
+    devices: Vec<(RangeInclusive<u16>, SharedDevice)>,
+}
+
+impl Ports {
+    /// Routes `ports` to `device`, ahead of anything attached to them before.
+    pub fn attach(&mut self, ports: RangeInclusive<u16>, device: SharedDevice) {
+        self.devices.insert(0, (ports, device));
+    }
+
+    pub fn read(&self, port: u16, wide: bool) -> u16 {
+        let lo = self.read_byte(port) as u16;
+        if !wide {
+            return lo;
+        }
+        let hi = self.read_byte(port.wrapping_add(1)) as u16;
+        lo | (hi << 8)
+    }
+
+    /// Writes the low byte of `value`, or all of it low byte first when `wide` is set.
+    pub fn write(&self, port: u16, value: u16, wide: bool) {
+        self.write_byte(port, value as u8);
+        if wide {
+            self.write_byte(port.wrapping_add(1), (value >> 8) as u8);
+        }
+    }
+
+    /// Polls every attached device, returning the interrupt vectors they request in
+    /// the order they were attached.
+    pub fn poll(&self) -> Vec<u8> {
+        self.devices
+            .iter()
+            .rev()
+            .filter_map(|(_, device)| device.borrow_mut().poll())
+            .collect()
+    }
+
+    fn device(&self, port: u16) -> Option<&SharedDevice> {
+        self.devices
+            .iter()
+            .find(|(ports, _)| ports.contains(&port))
+            .map(|(_, device)| device)
+    }
+
+    fn read_byte(&self, port: u16) -> u8 {
+        self.device(port)
+            .map_or(0xff, |device| device.borrow_mut().read(port))
+    }
+
+    fn write_byte(&self, port: u16, value: u8) {
+        if let Some(device) = self.device(port) {
+            device.borrow_mut().write(port, value);
+        }
+    }
+}
+
+/// Devices compare by identity, so a cloned simulator still equals the original.
+impl PartialEq for Ports {
+    fn eq(&self, other: &Self) -> bool {
+        self.devices.len() == other.devices.len()
+            && self
+                .devices
+                .iter()
+                .zip(&other.devices)
+                .all(|((a, x), (b, y))| a == b && Rc::ptr_eq(x, y))
+    }
+}
+
+impl Eq for Ports {}
@@ -0,0 +1,2009 @@
+//! An 8086 decoder, assembler and simulator.
+//!
+//! [`Instruction::try_from`] decodes a single instruction and [`disassemble`] a whole
+//! binary into nasm source. [`simulate`] executes a binary and returns a trace in
+//! the format of the listings' reference `.txt` files, and [`Simulator`] steps one
+//! directly. The `computer_enhance` binary is a command line front end to these.
+mod alu;
+pub mod assembler;
+pub mod clocks;
+mod decode;
+pub mod flags;
+pub mod image;
+pub mod instruction;
+pub mod io;
+pub mod memory;
+pub mod mode;
+pub mod opcode;
+pub mod operand;
+mod parser;
+pub mod register;
+mod register_file;
+pub mod simulator;
+mod table;
+pub mod text;
+
+use clocks::Cpu;
+use image::{ImageError, ImageFormat};
+use instruction::{Instruction, ParseInstructionError};
+use opcode::Opcode;
+use simulator::{SimulationError, Simulator};
+use std::fmt::Display;
+
+/// The longest 8086 instruction without prefixes: opcode, mod/rm, two displacement
+/// bytes and two data bytes.
+pub const MAX_INSTRUCTION_BYTES: usize = 6;
+
+/// The most prefixes the decoder takes in front of one instruction. The 8086 itself
+/// has no limit, but a lock, a segment override and a rep fit with plenty to spare.
+pub const MAX_PREFIXES: usize = 10;
+
+/// What [`disassemble`] does with bytes it can't decode.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DisassemblyMode {
+    /// Stop at the first byte that can't be decoded.
+    Strict,
+    /// Emit each undecodable byte as a `db` directive and resume decoding after it.
+    Lossy,
+}
+
+/// How [`simulate`] and [`run`] execute a program and what goes in the trace.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SimulateOptions {
+    /// Give up after this many instructions so a program that never finishes can't
+    /// hang the caller.
+    pub max_steps: usize,
+    /// Include `ip` in the trace. The traces before listing 0048 leave it out.
+    pub show_ip: bool,
+    /// Estimate clocks for this processor and explain them on every line.
+    pub clocks: Option<Cpu>,
+    /// Stop at the first ret or retf instead of executing it, like sim86's
+    /// `-stoponret`. The listings from 0059 on are functions that end in one.
+    pub stop_on_ret: bool,
+}
+
+impl Default for SimulateOptions {
+    fn default() -> Self {
+        Self {
+            max_steps: 1_000_000,
+            show_ip: true,
+            clocks: None,
+            stop_on_ret: false,
+        }
+    }
+}
+
+/// Decodes `input` from its first byte to its last and returns it as nasm source,
+/// one instruction per line after `bits 16`.
+pub fn disassemble(input: &[u8], mode: DisassemblyMode) -> Result<String, ParseInstructionError> {
+    let mut strs: Vec<String> = vec!["bits 16".to_string()];
+    let mut offset = 0;
+    while offset < input.len() {
+        let current = &input[offset..];
+        let instruction = match Instruction::try_from(current) {
+            Ok(instruction) => instruction,
+            Err(err) => {
+                if mode == DisassemblyMode::Strict {
+                    let end = current.len().min(MAX_INSTRUCTION_BYTES);
+                    return Err(err.at(offset, &current[..end]));
+                }
+                strs.push(format!("db {:#04x}", current[0]));
+                offset += 1;
+                continue;
+            }
+        };
+
+        strs.push(instruction.to_asm());
+        offset += instruction.bytes() as usize;
+    }
+    Ok(strs.join("\n"))
+}
+
+/// A run that stopped before the program finished.
+#[derive(Debug)]
+pub struct RunError {
+    pub error: SimulationError,
+    /// The trace up to the instruction that failed, ending with the registers at
+    /// that point, so a program that never finishes still shows what it did.
+    pub trace: String,
+}
+
+impl Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for RunError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Executes `input` from its first byte and returns the trace, formatted like the
+/// reference `listing_00xx.txt` files with `name` in the header.
+pub fn simulate(input: &[u8], name: &str, options: SimulateOptions) -> Result<String, RunError> {
+    run(input, name, options).map(|(trace, _)| trace)
+}
+
+/// Like [`simulate`], but also hands back the simulator so its final memory can be
+/// inspected.
+pub fn run(
+    input: &[u8],
+    name: &str,
+    options: SimulateOptions,
+) -> Result<(String, Simulator), RunError> {
+    let mut simulator = Simulator::new();
+    simulator.load(input);
+    let mut lines = Vec::new();
+    if options.clocks.is_some() {
+        lines.push(String::new());
+        lines.push(CLOCKS_WARNING.to_string());
+    }
+    lines.push(format!("--- {} execution ---", name));
+    let mut steps = 0;
+    let (mut total_clocks, mut total_max_clocks) = (0, 0);
+    // Calls and far jumps can move cs, so the end of the program is found by comparing
+    // physical addresses. Nothing raises external interrupts here, so hlt ends it too.
+    let start = simulator.code_address();
+    let mut execute = || -> Result<(), SimulationError> {
+        while !simulator.halted
+            && (simulator.code_address().wrapping_sub(start) as usize) < input.len()
+        {
+            if steps == options.max_steps {
+                return Err(SimulationError::StepLimit(steps));
+            }
+            if options.stop_on_ret
+                && matches!(simulator.fetch()?.opcode, Opcode::Ret | Opcode::Retf)
+            {
+                lines.push(format!(
+                    "STOPONRET: Return encountered at address {}.",
+                    simulator.code_address()
+                ));
+                break;
+            }
+            let before = simulator.snapshot();
+            let (instruction, outcome) = simulator.step()?;
+            let clocks = options.clocks.map(|cpu| {
+                let timing = clocks::estimate(&instruction, &outcome);
+                let penalty = clocks::penalty(timing, instruction.wide, cpu, outcome.unaligned);
+                let spent = timing.clocks() + penalty;
+                let max_spent = timing.max_clocks() + penalty;
+                total_clocks += spent;
+                total_max_clocks += max_spent;
+                // Once a range has been added, sim86 prints every count as one.
+                let ranged = |min: u32, max: u32| {
+                    if total_clocks == total_max_clocks {
+                        min.to_string()
+                    } else {
+                        format!("[{},{}]", min, max)
+                    }
+                };
+                format!(
+                    "Clocks: +{} = {}{} | ",
+                    ranged(spent, max_spent),
+                    ranged(total_clocks, total_max_clocks),
+                    clocks::explain(timing, penalty)
+                )
+            });
+            lines.push(format!(
+                "{} ; {}{}",
+                text::instruction_text(&instruction),
+                clocks.unwrap_or_default(),
+                text::register_diff(&before, &simulator.snapshot(), options.show_ip)
+            ));
+            steps += 1;
+        }
+        Ok(())
+    };
+    let stopped = execute();
+    lines.push(String::new());
+    lines.push(text::final_registers(
+        &simulator.snapshot(),
+        options.show_ip,
+    ));
+    lines.push(String::new());
+    let trace = lines.join("\n");
+    match stopped {
+        Ok(()) => Ok((trace, simulator)),
+        Err(error) => Err(RunError { error, trace }),
+    }
+}
+
+/// Printed ahead of traces with clocks, as sim86 does.
+const CLOCKS_WARNING: &str = "\
+WARNING: Clocks reported by this utility are strictly from the 8086 manual.
+They will be inaccurate, both because the manual clocks are estimates, and because
+some of the entries in the manual look highly suspicious and are probably typos.
+";
+
+/// Renders `width * height` RGBA pixels of `simulator`'s memory from `address` on.
+/// The region has to be non-empty and end within memory.
+pub fn export_image(
+    simulator: &Simulator,
+    format: ImageFormat,
+    address: u32,
+    width: usize,
+    height: usize,
+) -> Result<Vec<u8>, ImageError> {
+    if width == 0 || height == 0 {
+        return Err(ImageError::Empty);
+    }
+    let len = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(4))
+        .filter(|&len| {
+            (address as usize)
+                .checked_add(len)
+                .is_some_and(|end| end <= memory::MEMORY_SIZE)
+        })
+        .ok_or(ImageError::OutOfBounds {
+            address,
+            width,
+            height,
+        })?;
+    let pixels = simulator.memory.read_range(address, len);
+    Ok(image::encode(format, &pixels, width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flags::Flags;
+    use instruction::ParseErrorKind;
+    use operand::Operand;
+    use register::Register;
+    use std::{cell::RefCell, rc::Rc};
+
+    fn compare(actual: &str, expected_bin_path: &str) {
+        let actual_contents = assembler::assemble(actual).unwrap();
+        let expected_contents = std::fs::read(expected_bin_path).unwrap();
+        assert_eq!(actual_contents, expected_contents);
+    }
+
+    #[test]
+    fn correctly_handles_single_register_mov() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0037_single_register_mov").unwrap();
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        compare(&actual, "perfaware/part1/listing_0037_single_register_mov")
+    }
+
+    #[test]
+    fn correctly_handles_many_register_mov() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0038_many_register_mov").unwrap();
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        compare(&actual, "perfaware/part1/listing_0038_many_register_mov")
+    }
+
+    #[test]
+    fn correctly_handles_more_movs() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0039_more_movs").unwrap();
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        compare(&actual, "perfaware/part1/listing_0039_more_movs")
+    }
+
+    #[test]
+    fn correctly_handles_add_sub_cmp() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0046_add_sub_cmp").unwrap();
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        compare(&actual, "perfaware/part1/listing_0046_add_sub_cmp")
+    }
+
+    #[test]
+    fn correctly_handles_add_sub_cmp_jnz() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0041_add_sub_cmp_jnz").unwrap();
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        compare(&actual, "perfaware/part1/listing_0041_add_sub_cmp_jnz")
+    }
+
+    #[test]
+    fn correctly_handles_conditional_jumps() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0049_conditional_jumps").unwrap();
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        compare(&actual, "perfaware/part1/listing_0049_conditional_jumps")
+    }
+
+    #[test]
+    fn correctly_handles_challenge_jumps() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0050_challenge_jumps").unwrap();
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        compare(&actual, "perfaware/part1/listing_0050_challenge_jumps")
+    }
+
+    #[test]
+    fn correctly_handles_more_movs_challenge() {
+        // Arrange
+        let binary_file = "perfaware/part1/listing_0040_challenge_movs";
+        let input = std::fs::read(binary_file).unwrap();
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        compare(&actual, "perfaware/part1/listing_0040_challenge_movs")
+    }
+
+    #[test]
+    fn correctly_handles_completionist_decode() {
+        // Arrange
+        let binary_file = "perfaware/part1/listing_0042_completionist_decode";
+        let input = std::fs::read(binary_file).unwrap();
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        compare(&actual, "perfaware/part1/listing_0042_completionist_decode")
+    }
+
+    const EFFECTIVE_ADDRESSES: [&str; 8] = [
+        "bx + si", "bx + di", "bp + si", "bp + di", "si", "di", "bp", "bx",
+    ];
+    const BYTE_REGISTERS: [&str; 8] = ["al", "cl", "dl", "bl", "ah", "ch", "dh", "bh"];
+    const WORD_REGISTERS: [&str; 8] = ["ax", "cx", "dx", "bx", "sp", "bp", "si", "di"];
+
+    #[test]
+    fn immediate_movs_decode_every_mod_rm_and_width() {
+        for wide in [false, true] {
+            for r#mod in 0..4u8 {
+                for rm in 0..8u8 {
+                    // Arrange
+                    // Negative displacements and data, so sign handling shows up everywhere.
+                    let mut input = vec![0xc6 | wide as u8, (r#mod << 6) | rm];
+                    let (disp, address) = match (r#mod, rm) {
+                        (0b00, 0b110) => (vec![0xfe, 0xff], "[65534]".to_string()),
+                        (0b00, _) => (vec![], format!("[{}]", EFFECTIVE_ADDRESSES[rm as usize])),
+                        (0b01, _) => (
+                            vec![0x80],
+                            format!("[{} - 128]", EFFECTIVE_ADDRESSES[rm as usize]),
+                        ),
+                        (0b10, _) => (
+                            vec![0x18, 0xfc],
+                            format!("[{} - 1000]", EFFECTIVE_ADDRESSES[rm as usize]),
+                        ),
+                        _ => (vec![], String::new()),
+                    };
+                    input.extend(disp);
+                    let (data, value, size): (&[u8], _, _) = if wide {
+                        (&[0x00, 0x80], "-32768", "word")
+                    } else {
+                        (&[0xf0], "-16", "byte")
+                    };
+                    input.extend(data);
+                    let expected = if r#mod == 0b11 {
+                        let registers = if wide { WORD_REGISTERS } else { BYTE_REGISTERS };
+                        format!("bits 16\nmov {}, {}", registers[rm as usize], value)
+                    } else {
+                        format!("bits 16\nmov {}, {} {}", address, size, value)
+                    };
+                    // Act
+                    let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+                    // Assert
+                    assert_eq!(actual, expected, "{:02x?}", input);
+                    let reassembled = assembler::assemble(&actual).unwrap();
+                    if r#mod == 0b11 {
+                        // nasm picks the shorter b0+reg form for a register, which still
+                        // has to mean the same thing.
+                        let again = disassemble(&reassembled, DisassemblyMode::Strict);
+                        assert_eq!(again.unwrap(), expected);
+                    } else {
+                        assert_eq!(reassembled, input);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn accumulator_movs_decode_both_directions_and_widths() {
+        for opcode in 0xa0..=0xa3u8 {
+            for address in [0x0000u16, 0x7fff, 0x8000, 0xffff] {
+                // Arrange
+                let mut input = vec![opcode];
+                input.extend(address.to_le_bytes());
+                let accumulator = if opcode & 1 == 1 { "ax" } else { "al" };
+                let expected = if opcode & 2 == 0 {
+                    format!("bits 16\nmov {}, [{}]", accumulator, address)
+                } else {
+                    format!("bits 16\nmov [{}], {}", address, accumulator)
+                };
+                // Act
+                let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+                // Assert
+                assert_eq!(actual, expected);
+                assert_eq!(assembler::assemble(&actual).unwrap(), input);
+            }
+        }
+    }
+
+    #[test]
+    fn register_form_of_the_immediate_mov_writes_the_register() {
+        // Arrange
+        let mut simulator = Simulator::new();
+        simulator.registers.write(Register::CX, 0x1234);
+        // mov cl, -1 ; mov dx, -32768, both in their c6/c7 encodings
+        let byte = Instruction::try_from(&[0xc6, 0xc1, 0xff][..]).unwrap();
+        let word = Instruction::try_from(&[0xc7, 0xc2, 0x00, 0x80][..]).unwrap();
+        // Act
+        simulator.execute(&byte).unwrap();
+        simulator.execute(&word).unwrap();
+        // Assert
+        assert_eq!(simulator.registers.read(Register::CX), 0x12ff);
+        assert_eq!(simulator.registers.read(Register::DX), 0x8000);
+        assert!(simulator.memory.as_bytes().iter().all(|&byte| byte == 0));
+    }
+
+    /// Every case is padded with nops to this many bytes, so a disagreement about one
+    /// instruction's length can't spill into the cases after it.
+    const CONFORMANCE_SLOT: usize = 8;
+    /// What follows the opcode and ModRM byte: displacements and immediates that are
+    /// positive, then negative. Each byte is also a one byte instruction of its own.
+    const CONFORMANCE_TAILS: [[u8; 4]; 2] = [[0x47, 0x53, 0x41, 0x5a], [0x9f, 0xfc, 0x98, 0x9e]];
+    const UNDECODABLE: &str = "(undecodable)";
+
+    /// `opcode` followed by every second byte and each tail, one case per slot.
+    fn conformance_cases(opcode: u8) -> Vec<u8> {
+        let mut input = Vec::new();
+        for second in 0..=u8::MAX {
+            for tail in CONFORMANCE_TAILS {
+                let start = input.len();
+                input.extend([opcode, second]);
+                input.extend(tail);
+                input.resize(start + CONFORMANCE_SLOT, 0x90);
+            }
+        }
+        input
+    }
+
+    /// Our decoding in sim86's syntax, with the offset each instruction starts at.
+    fn conformance_decode(input: &[u8]) -> Vec<(usize, String)> {
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        while offset < input.len() {
+            let Ok(instruction) = Instruction::try_from(&input[offset..]) else {
+                lines.push((offset, UNDECODABLE.to_string()));
+                break;
+            };
+            let mut line = text::instruction_text(&instruction).trim_end().to_string();
+            // The reference leaves the size off string instructions without a rep.
+            if instruction.opcode.is_string() && instruction.repeat.is_none() {
+                line.pop();
+            }
+            lines.push((offset, line));
+            offset += instruction.size as usize;
+        }
+        lines
+    }
+
+    /// The reference's decoding of `input`, which stops at the first byte it can't decode.
+    fn reference_decode(sim86: &str, input: &[u8]) -> Vec<String> {
+        let path = std::env::temp_dir().join(format!("conformance_{}", std::process::id()));
+        std::fs::write(&path, input).unwrap();
+        let output = std::process::Command::new(sim86)
+            .arg(&path)
+            .output()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let mut lines: Vec<String> = stdout
+            .lines()
+            .filter(|l| !l.starts_with(';') && !l.starts_with("bits"))
+            .map(|l| {
+                let mut words: Vec<&str> = l.trim_end().split(' ').collect();
+                // It puts a size after anything with a rep, not just string instructions.
+                if let ["rep" | "repne", mnemonic, ..] = &mut words[..] {
+                    let bare = &mnemonic[..mnemonic.len() - 1];
+                    if !["movs", "cmps", "stos", "lods", "scas"].contains(&bare) {
+                        *mnemonic = bare;
+                    }
+                }
+                words.join(" ")
+            })
+            .collect();
+        if String::from_utf8_lossy(&output.stderr).contains("Unrecognized") {
+            lines.push(UNDECODABLE.to_string());
+        }
+        lines
+    }
+
+    /// Compares every opcode byte against Casey's decoder, run as a sim86 built from
+    /// perfaware/sim86 (`g++ -O2 -fpermissive -o sim86 perfaware/sim86/sim86.cpp`) and
+    /// named by the `SIM86` environment variable. Run it with `cargo test -- --ignored`.
+    #[test]
+    #[ignore = "needs SIM86 pointing at a sim86 build"]
+    fn decoder_agrees_with_the_reference_decoder() {
+        let sim86 = std::env::var("SIM86").expect("SIM86 has to point at a sim86 build");
+        let mut mismatches = Vec::new();
+        for opcode in 0..=u8::MAX {
+            // The reference's esc pattern reuses the data field for the opcode bits, so it
+            // reads an immediate that isn't there.
+            if (0xd8..=0xdf).contains(&opcode) {
+                continue;
+            }
+            // Arrange
+            let input = conformance_cases(opcode);
+            // Act
+            let ours = conformance_decode(&input);
+            let theirs = reference_decode(&sim86, &input);
+            // Assert
+            let Some(index) = (0..ours.len().max(theirs.len()))
+                .find(|&i| ours.get(i).map(|(_, line)| line) != theirs.get(i))
+            else {
+                continue;
+            };
+            // After a disagreement the two decodings are out of step, so only the first
+            // one in each opcode's cases is worth reporting.
+            let offset = ours.get(index).map_or(input.len(), |&(offset, _)| offset);
+            let start = offset - offset % CONFORMANCE_SLOT;
+            let mnemonic =
+                |line: Option<&String>| line.and_then(|l| l.split(' ').next()).map(str::to_string);
+            let kind = if offset != start {
+                // The slot's instruction decoded the same, but we ended it somewhere else.
+                "length"
+            } else if mnemonic(ours.get(index).map(|(_, l)| l)) != mnemonic(theirs.get(index)) {
+                "mnemonic"
+            } else {
+                "operands"
+            };
+            mismatches.push(format!(
+                "{} differs in {:02x?}: ours {:?}, reference {:?}",
+                kind,
+                &input[start..start + CONFORMANCE_SLOT],
+                ours.get(index).map(|(_, l)| l),
+                theirs.get(index),
+            ));
+        }
+        assert!(mismatches.is_empty(), "\n{}", mismatches.join("\n"));
+    }
+
+    /// The traces before listing 0048 were recorded before sim86 printed `ip`.
+    const BEFORE_IP: SimulateOptions = SimulateOptions {
+        max_steps: 1000,
+        show_ip: false,
+        clocks: None,
+        stop_on_ret: false,
+    };
+
+    fn expected_trace(path: &str) -> String {
+        std::fs::read_to_string(path).unwrap().replace("\r\n", "\n")
+    }
+
+    /// The cycle listings hold one trace per processor, each under a banner like
+    /// `**** 8086 ****`.
+    fn expected_clocks_trace(path: &str, cpu: &str) -> String {
+        let expected = expected_trace(path);
+        let banner = format!("**** {} ****\n**************\n", cpu);
+        let start = expected.find(&banner).unwrap() + banner.len();
+        let section = expected[start..].split("\n**************").next().unwrap();
+        format!("{}\n\n", section.trim_end())
+    }
+
+    /// The 8088 traces lost their trailing spaces along the way.
+    fn trim_lines(text: &str) -> String {
+        text.lines()
+            .map(str::trim_end)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// One processor's trace from a listing's `.txt`, and the options it was recorded with.
+    struct GoldenTrace {
+        name: String,
+        binary: String,
+        expected: String,
+        options: SimulateOptions,
+    }
+
+    /// Every `listing_*.txt` in part 1, split per processor where a listing has several.
+    fn golden_traces() -> Vec<GoldenTrace> {
+        let mut paths: Vec<String> = std::fs::read_dir("perfaware/part1")
+            .unwrap()
+            .map(|entry| entry.unwrap().path().to_string_lossy().into_owned())
+            .filter(|path| path.contains("/listing_") && path.ends_with(".txt"))
+            .collect();
+        paths.sort();
+        let mut traces = Vec::new();
+        for path in paths {
+            let binary = path.trim_end_matches(".txt").to_string();
+            let listing = binary.rsplit('/').next().unwrap().to_string();
+            let text = expected_trace(&path);
+            let sections = if text.contains("**** 8086 ****") {
+                [("8086", Cpu::I8086), ("8088", Cpu::I8088)]
+                    .into_iter()
+                    .filter(|(banner, _)| text.contains(&format!("**** {} ****", banner)))
+                    .map(|(banner, cpu)| {
+                        let name = format!("{} ({})", listing, banner);
+                        (name, Some(cpu), expected_clocks_trace(&path, banner))
+                    })
+                    .collect()
+            } else {
+                let cpu = text.contains(CLOCKS_WARNING.lines().next().unwrap());
+                vec![(listing, cpu.then_some(Cpu::I8086), text)]
+            };
+            for (name, clocks, expected) in sections {
+                let options = SimulateOptions {
+                    show_ip: expected.contains(" ip:"),
+                    clocks,
+                    stop_on_ret: expected.contains("STOPONRET:"),
+                    ..SimulateOptions::default()
+                };
+                traces.push(GoldenTrace {
+                    name,
+                    binary: binary.clone(),
+                    expected,
+                    options,
+                });
+            }
+        }
+        traces
+    }
+
+    /// The lines worth comparing: without trailing spaces, which the 8088 traces lost,
+    /// and with the header naming just the listing, not the `test\` directory sim86 ran
+    /// it from.
+    fn normalise_trace(trace: &str) -> Vec<String> {
+        let mut lines: Vec<String> = trace
+            .lines()
+            .map(|line| {
+                let line = line.trim_end();
+                match line
+                    .strip_prefix("--- ")
+                    .and_then(|line| line.strip_suffix(" execution ---"))
+                {
+                    Some(path) => {
+                        let listing = path.rsplit(['\\', '/']).next().unwrap();
+                        format!("--- {} execution ---", listing)
+                    }
+                    None => line.to_string(),
+                }
+            })
+            .collect();
+        while lines.last().is_some_and(String::is_empty) {
+            lines.pop();
+        }
+        lines
+    }
+
+    /// Runs `trace`'s binary and describes the first line that differs from the trace,
+    /// with the registers and flags just before the instruction that printed it.
+    fn golden_divergence(trace: &GoldenTrace) -> Option<String> {
+        let input = std::fs::read(&trace.binary).unwrap();
+        let actual = match simulate(&input, &trace.binary, trace.options) {
+            Ok(actual) => normalise_trace(&actual),
+            Err(err) => return Some(format!("{}: {:?}", trace.name, err.error)),
+        };
+        let expected = normalise_trace(&trace.expected);
+        let index =
+            (0..expected.len().max(actual.len())).find(|&i| expected.get(i) != actual.get(i))?;
+        let mut report = format!(
+            "{}, line {}:\n  expected {:?}\n  actual   {:?}",
+            trace.name,
+            index + 1,
+            expected.get(index),
+            actual.get(index)
+        );
+        let header = expected
+            .iter()
+            .position(|line| line.ends_with(" execution ---"));
+        if let Some(steps) = header.and_then(|header| index.checked_sub(header + 1)) {
+            let executed = expected[index - steps..]
+                .iter()
+                .take_while(|line| !line.is_empty() && !line.starts_with("STOPONRET:"))
+                .count();
+            let mut simulator = Simulator::new();
+            simulator.load(&input);
+            for _ in 0..steps.min(executed) {
+                if simulator.step().is_err() {
+                    break;
+                }
+            }
+            if steps < executed {
+                report.push_str(&format!("\n  before instruction {}:", steps + 1));
+            } else {
+                report.push_str("\n  after the last instruction:");
+            }
+            let state = text::final_registers(&simulator.snapshot(), true);
+            for line in state.lines().skip(1) {
+                report.push_str(&format!("\n  {}", line));
+            }
+        }
+        Some(report)
+    }
+
+    #[test]
+    fn simulates_every_golden_trace() {
+        // Arrange
+        let traces = golden_traces();
+        // Act
+        let divergences: Vec<String> = traces.iter().filter_map(golden_divergence).collect();
+        // Assert
+        assert!(traces.iter().any(|trace| trace.name.ends_with("(8088)")));
+        assert!(traces.len() > 20, "only found {} traces", traces.len());
+        assert!(divergences.is_empty(), "\n{}", divergences.join("\n\n"));
+    }
+
+    #[test]
+    fn golden_divergences_show_the_state_before_the_instruction() {
+        // Arrange
+        let path = "perfaware/part1/listing_0044_register_movs.txt";
+        let trace = GoldenTrace {
+            name: "listing_0044_register_movs".to_string(),
+            binary: "perfaware/part1/listing_0044_register_movs".to_string(),
+            expected: expected_trace(path).replace("dx:0x0->0x4", "dx:0x0->0x5"),
+            options: BEFORE_IP,
+        };
+        // Act
+        let actual = golden_divergence(&trace).unwrap();
+        // Assert
+        let expected = "\
+listing_0044_register_movs, line 5:
+  expected Some(\"mov dx, 4 ; dx:0x0->0x5\")
+  actual   Some(\"mov dx, 4 ; dx:0x0->0x4\")
+  before instruction 4:
+        ax: 0x0001 (1)
+        bx: 0x0002 (2)
+        cx: 0x0003 (3)
+        ip: 0x0009 (9)";
+        assert_eq!(actual.trim_end(), expected);
+    }
+
+    #[test]
+    fn simulates_immediate_movs() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0043_immediate_movs").unwrap();
+        // Act
+        let actual = simulate(&input, "test\\listing_0043_immediate_movs", BEFORE_IP).unwrap();
+        // Assert
+        let expected = expected_trace("perfaware/part1/listing_0043_immediate_movs.txt");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn simulates_register_movs() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0044_register_movs").unwrap();
+        // Act
+        let actual = simulate(&input, "test\\listing_0044_register_movs", BEFORE_IP).unwrap();
+        // Assert
+        let expected = expected_trace("perfaware/part1/listing_0044_register_movs.txt");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn simulates_challenge_register_movs() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0045_challenge_register_movs").unwrap();
+        // Act
+        let actual = simulate(
+            &input,
+            "test\\listing_0045_challenge_register_movs",
+            BEFORE_IP,
+        )
+        .unwrap();
+        // Assert
+        let expected = expected_trace("perfaware/part1/listing_0045_challenge_register_movs.txt");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn simulates_add_sub_cmp_flags() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0046_add_sub_cmp").unwrap();
+        // Act
+        let actual = simulate(&input, "test\\listing_0046_add_sub_cmp", BEFORE_IP).unwrap();
+        // Assert
+        let expected = expected_trace("perfaware/part1/listing_0046_add_sub_cmp.txt");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn simulates_challenge_flags() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0047_challenge_flags").unwrap();
+        // Act
+        let actual = simulate(&input, "test\\listing_0047_challenge_flags", BEFORE_IP).unwrap();
+        // Assert
+        let expected = expected_trace("perfaware/part1/listing_0047_challenge_flags.txt");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn simulates_ip_register() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0048_ip_register").unwrap();
+        // Act
+        let actual = simulate(
+            &input,
+            "test\\listing_0048_ip_register",
+            SimulateOptions::default(),
+        )
+        .unwrap();
+        // Assert
+        let expected = expected_trace("perfaware/part1/listing_0048_ip_register.txt");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn simulates_conditional_jumps() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0049_conditional_jumps").unwrap();
+        // Act
+        let actual = simulate(
+            &input,
+            "test\\listing_0049_conditional_jumps",
+            SimulateOptions::default(),
+        )
+        .unwrap();
+        // Assert
+        let expected = expected_trace("perfaware/part1/listing_0049_conditional_jumps.txt");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn simulates_challenge_jumps() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0050_challenge_jumps").unwrap();
+        // Act
+        let actual = simulate(
+            &input,
+            "test\\listing_0050_challenge_jumps",
+            SimulateOptions::default(),
+        )
+        .unwrap();
+        // Assert
+        let expected = expected_trace("perfaware/part1/listing_0050_challenge_jumps.txt");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn simulates_memory_movs() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0051_memory_mov").unwrap();
+        // Act
+        let actual = simulate(
+            &input,
+            "test\\listing_0051_memory_mov",
+            SimulateOptions::default(),
+        )
+        .unwrap();
+        // Assert
+        let expected = expected_trace("perfaware/part1/listing_0051_memory_mov.txt");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn simulates_memory_add_loop() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0052_memory_add_loop").unwrap();
+        // Act
+        let actual = simulate(
+            &input,
+            "test\\listing_0052_memory_add_loop",
+            SimulateOptions::default(),
+        )
+        .unwrap();
+        // Assert
+        let expected = expected_trace("perfaware/part1/listing_0052_memory_add_loop.txt");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn simulates_add_loop_challenge() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0053_add_loop_challenge").unwrap();
+        // Act
+        let actual = simulate(
+            &input,
+            "test\\listing_0053_add_loop_challenge",
+            SimulateOptions::default(),
+        )
+        .unwrap();
+        // Assert
+        let expected = expected_trace("perfaware/part1/listing_0053_add_loop_challenge.txt");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn simulates_draw_rectangle() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0054_draw_rectangle").unwrap();
+        // Act
+        let actual = simulate(
+            &input,
+            "test\\listing_0054_draw_rectangle",
+            SimulateOptions::default(),
+        )
+        .unwrap();
+        // Assert
+        let expected = expected_trace("perfaware/part1/listing_0054_draw_rectangle.txt");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn simulates_challenge_rectangle() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0055_challenge_rectangle").unwrap();
+        // Act
+        let actual = simulate(
+            &input,
+            "test\\listing_0055_challenge_rectangle",
+            SimulateOptions::default(),
+        )
+        .unwrap();
+        // Assert
+        let expected = expected_trace("perfaware/part1/listing_0055_challenge_rectangle.txt");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn exports_rectangle_as_ppm() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0054_draw_rectangle").unwrap();
+        let (_, simulator) = run(
+            &input,
+            "listing_0054_draw_rectangle",
+            SimulateOptions::default(),
+        )
+        .unwrap();
+        // Act
+        let actual = export_image(&simulator, ImageFormat::Ppm, 256, 64, 64).unwrap();
+        // Assert
+        let expected = std::fs::read("golden/listing_0054_draw_rectangle.ppm").unwrap();
+        assert!(
+            actual == expected,
+            "image differs from golden/listing_0054_draw_rectangle.ppm"
+        );
+    }
+
+    #[test]
+    fn image_regions_outside_memory_are_errors() {
+        // Arrange
+        let simulator = Simulator::new();
+        // Act
+        let regions = [
+            (0, 100_000, 100_000),
+            (0, usize::MAX, 2),
+            (memory::MEMORY_SIZE as u32 - 4, 2, 1),
+            (0, 0, 64),
+        ];
+        let errors: Vec<_> = regions
+            .iter()
+            .map(|&(address, width, height)| {
+                export_image(&simulator, ImageFormat::Ppm, address, width, height).unwrap_err()
+            })
+            .collect();
+        // Assert
+        assert!(matches!(errors[0], ImageError::OutOfBounds { .. }));
+        assert!(matches!(errors[1], ImageError::OutOfBounds { .. }));
+        assert!(matches!(errors[2], ImageError::OutOfBounds { .. }));
+        assert_eq!(errors[3], ImageError::Empty);
+        let last_pixel = memory::MEMORY_SIZE as u32 - 4;
+        assert!(export_image(&simulator, ImageFormat::Ppm, last_pixel, 1, 1).is_ok());
+    }
+
+    #[test]
+    fn exports_challenge_rectangle_as_png() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0055_challenge_rectangle").unwrap();
+        let (_, simulator) = run(
+            &input,
+            "listing_0055_challenge_rectangle",
+            SimulateOptions::default(),
+        )
+        .unwrap();
+        // Act
+        let actual = export_image(&simulator, ImageFormat::Png, 256, 64, 64).unwrap();
+        // Assert
+        let expected = std::fs::read("golden/listing_0055_challenge_rectangle.png").unwrap();
+        assert!(
+            actual == expected,
+            "image differs from golden/listing_0055_challenge_rectangle.png"
+        );
+    }
+
+    #[test]
+    fn estimates_cycles_on_the_8086() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0056_estimating_cycles").unwrap();
+        let options = SimulateOptions {
+            clocks: Some(Cpu::I8086),
+            ..SimulateOptions::default()
+        };
+        // Act
+        let actual = simulate(&input, "test\\listing_0056_estimating_cycles", options).unwrap();
+        // Assert
+        let expected =
+            expected_clocks_trace("perfaware/part1/listing_0056_estimating_cycles.txt", "8086");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn estimates_cycles_on_the_8088() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0056_estimating_cycles").unwrap();
+        let options = SimulateOptions {
+            clocks: Some(Cpu::I8088),
+            ..SimulateOptions::default()
+        };
+        // Act
+        let actual = simulate(&input, "test\\listing_0056_estimating_cycles", options).unwrap();
+        // Assert
+        let expected =
+            expected_clocks_trace("perfaware/part1/listing_0056_estimating_cycles.txt", "8088");
+        assert_eq!(trim_lines(&actual), trim_lines(&expected));
+    }
+
+    #[test]
+    fn estimates_challenge_cycles_on_the_8086() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0057_challenge_cycles").unwrap();
+        let options = SimulateOptions {
+            clocks: Some(Cpu::I8086),
+            ..SimulateOptions::default()
+        };
+        // Act
+        let actual = simulate(&input, "test\\listing_0057_challenge_cycles", options).unwrap();
+        // Assert
+        let expected =
+            expected_clocks_trace("perfaware/part1/listing_0057_challenge_cycles.txt", "8086");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn estimates_challenge_cycles_on_the_8088() {
+        // Arrange
+        let input = std::fs::read("perfaware/part1/listing_0057_challenge_cycles").unwrap();
+        let options = SimulateOptions {
+            clocks: Some(Cpu::I8088),
+            ..SimulateOptions::default()
+        };
+        // Act
+        let actual = simulate(&input, "test\\listing_0057_challenge_cycles", options).unwrap();
+        // Assert
+        let expected =
+            expected_clocks_trace("perfaware/part1/listing_0057_challenge_cycles.txt", "8088");
+        assert_eq!(trim_lines(&actual), trim_lines(&expected));
+    }
+
+    #[test]
+    fn effective_address_clocks_cover_every_mod_and_rm() {
+        // Arrange
+        // Rows are mod 00, 01 and 10; columns are rm 000 through 111. A displacement
+        // of zero costs nothing extra, so the mod 01/10 rows use a nonzero one.
+        let expected = [
+            [7, 8, 8, 7, 5, 5, 6, 5],
+            [11, 12, 12, 11, 9, 9, 9, 9],
+            [11, 12, 12, 11, 9, 9, 9, 9],
+        ];
+        for (r#mod, row) in expected.iter().enumerate() {
+            for (rm, &cost) in row.iter().enumerate() {
+                let direct = r#mod == 0 && rm == 0b110;
+                let disp = if r#mod == 0 && !direct { 0 } else { 100 };
+                let operand = Operand::effective_address(rm as u8, direct, disp);
+                // Act
+                let actual = clocks::ea_clocks(&operand);
+                // Assert
+                assert_eq!(actual, cost, "mod {:02b} rm {:03b}", r#mod, rm);
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_segment_override_prefixes() {
+        // Arrange
+        // es: mov dx, [bp] ; mov ds, ax ; mov si, es
+        let input = [0x26, 0x8b, 0x56, 0x00, 0x8e, 0xd8, 0x8c, 0xc6];
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        assert_eq!(actual, "bits 16\nmov dx, es:[bp]\nmov ds, ax\nmov si, es");
+        assert_eq!(assembler::assemble(&actual).unwrap(), input);
+    }
+
+    #[test]
+    fn segment_overrides_replace_the_default_segment() {
+        // Arrange
+        let mut simulator = Simulator::new();
+        simulator.registers.write(Register::ES, 0x3000);
+        simulator.registers.write(Register::BP, 0x10);
+        simulator.registers.write(Register::AX, 0x1234);
+        // mov es:[bp + 0], ax
+        let instruction = Instruction::try_from(&[0x26, 0x89, 0x46, 0x00][..]).unwrap();
+        // Act
+        simulator.execute(&instruction).unwrap();
+        // Assert
+        assert_eq!(text::instruction_text(&instruction), "mov word es:[bp], ax");
+        assert_eq!(simulator.memory.as_bytes()[0x30010..0x30012], [0x34, 0x12]);
+    }
+
+    #[test]
+    fn trace_text_drops_overrides_nothing_uses() {
+        // Arrange
+        // es inc di ; cs rep movsb
+        let inc = Instruction::try_from(&[0x26, 0x47][..]).unwrap();
+        let movs = Instruction::try_from(&[0x2e, 0xf3, 0xa4][..]).unwrap();
+        // Act
+        let actual = [text::instruction_text(&inc), text::instruction_text(&movs)];
+        // Assert
+        // sim86 only ever shows an override inside a memory operand.
+        assert_eq!(actual, ["inc di", "rep movsb "]);
+        assert_eq!(inc.to_asm(), "es inc di");
+    }
+
+    #[test]
+    fn decodes_stack_operations_and_far_transfers() {
+        // Arrange
+        let input = [
+            0xff, 0x32, 0x0e, 0x9d, 0x50, 0x8f, 0x02, 0x1f, 0x9c, 0xff, 0x56, 0x9c, 0x9a, 0xc8,
+            0x01, 0x7b, 0x00, 0xff, 0x2d, 0xc2, 0xf9, 0xff, 0xcb,
+        ];
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        let expected = "bits 16\npush word [bp + si]\npush cs\npopf\npush ax\n\
+            pop word [bp + si]\npop ds\npushf\ncall word [bp - 100]\ncall 123:456\n\
+            jmp far [di]\nret -7\nretf";
+        assert_eq!(actual, expected);
+        assert_eq!(assembler::assemble(&actual).unwrap(), input);
+    }
+
+    #[test]
+    fn calls_and_returns_go_through_the_stack() {
+        // Arrange
+        // The short jmp puts far_part at offset 2.
+        let source = "\
+            jmp start
+        far_part:
+            mov cx, 7
+            retf 2
+        start:
+            mov sp, 0x100
+            mov ax, 5
+            push ax
+            call double
+            pop bx
+            pushf
+            cmp ax, ax
+            popf
+            push ax
+            call 0:2
+            jmp done
+        double:
+            mov bp, sp
+            add word [bp + 2], 4
+            ret
+        done:
+        ";
+        let input = assembler::assemble(source).unwrap();
+        // Act
+        let (_, simulator) = run(&input, "stack", SimulateOptions::default()).unwrap();
+        // Assert
+        assert_eq!(simulator.registers.read(Register::BX), 9);
+        assert_eq!(simulator.registers.read(Register::CX), 7);
+        assert_eq!(simulator.registers.read(Register::SP), 0x100);
+        assert_eq!(simulator.flags.to_string(), "P");
+        assert_eq!(simulator.ip as usize, input.len());
+    }
+
+    #[test]
+    fn decodes_string_instructions_with_prefixes() {
+        // Arrange
+        let input = [
+            0xfc, 0xf3, 0xa4, 0xf2, 0xa6, 0xf3, 0xa7, 0xf2, 0xae, 0xad, 0xaa, 0xfd, 0x26, 0xa4,
+        ];
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        let expected = "bits 16\ncld\nrep movsb\nrepne cmpsb\nrep cmpsw\nrepne scasb\n\
+            lodsw\nstosb\nstd\nes movsb";
+        assert_eq!(actual, expected);
+        assert_eq!(assembler::assemble(&actual).unwrap(), input);
+    }
+
+    #[test]
+    fn rep_movs_copies_forwards_and_rep_stos_fills_backwards() {
+        // Arrange
+        let mut simulator = Simulator::new();
+        simulator.registers.write(Register::DS, 0x1000);
+        simulator.registers.write(Register::ES, 0x2000);
+        simulator.memory.load(0x10000, &[1, 2, 3, 4, 5, 6]);
+        simulator.registers.write(Register::CX, 3);
+        // rep movsw ; std ; rep stosb
+        let movsw = Instruction::try_from(&[0xf3, 0xa5][..]).unwrap();
+        let std = Instruction::try_from(&[0xfd][..]).unwrap();
+        let stosb = Instruction::try_from(&[0xf3, 0xaa][..]).unwrap();
+        // Act
+        simulator.execute(&movsw).unwrap();
+        simulator.execute(&std).unwrap();
+        simulator.registers.write(Register::AL, 0xee);
+        simulator.registers.write(Register::CX, 2);
+        simulator.execute(&stosb).unwrap();
+        // Assert
+        assert_eq!(
+            simulator.memory.read_range(0x20000, 8),
+            [1, 2, 3, 4, 5, 0xee, 0xee, 0]
+        );
+        assert_eq!(simulator.registers.read(Register::SI), 6);
+        assert_eq!(simulator.registers.read(Register::DI), 4);
+        assert_eq!(simulator.registers.read(Register::CX), 0);
+    }
+
+    #[test]
+    fn repeated_comparisons_stop_on_the_zero_flag() {
+        // Arrange
+        let mut simulator = Simulator::new();
+        simulator.memory.load(0, b"abxd");
+        simulator.memory.load(0x10, b"abyd");
+        simulator.registers.write(Register::DI, 0x10);
+        simulator.registers.write(Register::CX, 4);
+        // repe cmpsb ; repne scasb
+        let cmpsb = Instruction::try_from(&[0xf3, 0xa6][..]).unwrap();
+        let scasb = Instruction::try_from(&[0xf2, 0xae][..]).unwrap();
+        // Act
+        let compared = simulator.execute(&cmpsb).unwrap();
+        let after_compare = (
+            simulator.registers.read(Register::CX),
+            simulator.flags.contains(Flags::ZERO),
+        );
+        simulator.registers.write(Register::AL, b'd' as u16);
+        simulator.registers.write(Register::DI, 0);
+        simulator.registers.write(Register::CX, 4);
+        let scanned = simulator.execute(&scasb).unwrap();
+        // Assert
+        assert_eq!((compared.repetitions, after_compare), (3, (1, false)));
+        assert_eq!(scanned.repetitions, 4);
+        assert_eq!(simulator.registers.read(Register::DI), 4);
+        assert!(simulator.flags.contains(Flags::ZERO));
+        let timing = clocks::estimate(&scasb, &scanned);
+        assert_eq!(timing.clocks(), 9 + 4 * 15);
+        assert_eq!(clocks::explain(timing, 0), " (9 + 4x15rep)");
+    }
+
+    #[test]
+    fn decodes_shifts_and_logical_operations() {
+        // Arrange
+        let input = [
+            0xd0, 0xe4, 0xd3, 0x66, 0x05, 0xd2, 0x0e, 0x4a, 0x13, 0xf6, 0xd4, 0x21, 0xf4, 0x80,
+            0x66, 0xd9, 0xef, 0x84, 0xb6, 0x86, 0x01, 0xa9, 0x65, 0x5d, 0x32, 0x0e, 0x20, 0x11,
+        ];
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        let expected = "bits 16\nshl ah, 1\nshl word [bp + 5], cl\nror byte [4938], cl\n\
+            not ah\nand sp, si\nand [bp - 39], byte -17\ntest [bp + 390], dh\n\
+            test ax, 23909\nxor cl, [4384]";
+        assert_eq!(actual, expected);
+        assert_eq!(assembler::assemble(&actual).unwrap(), input);
+    }
+
+    #[test]
+    fn shifts_set_carry_from_the_last_bit_out() {
+        // Arrange
+        let mut simulator = Simulator::new();
+        simulator.registers.write(Register::SI, 0x8001);
+        simulator.registers.write(Register::BX, 0x7ff1);
+        simulator.registers.write(Register::CL, 3);
+        // shl si, 1 ; shl bx, cl ; rcr si, 1
+        let shl_one = Instruction::try_from(&[0xd1, 0xe6][..]).unwrap();
+        let shl_cl = Instruction::try_from(&[0xd3, 0xe3][..]).unwrap();
+        let rcr_one = Instruction::try_from(&[0xd1, 0xde][..]).unwrap();
+        // Act
+        simulator.execute(&shl_one).unwrap();
+        let after_one = simulator.flags.to_string();
+        let shifted = simulator.execute(&shl_cl).unwrap();
+        let after_cl = simulator.flags.to_string();
+        simulator.execute(&rcr_one).unwrap();
+        // Assert
+        assert_eq!(after_one, "CO");
+        // OF is only defined for a count of one, so it keeps its old value.
+        assert_eq!(after_cl, "CPSO");
+        assert_eq!(shifted.repetitions, 3);
+        assert_eq!(simulator.registers.read(Register::BX), 0xff88);
+        // Rotating through carry brings CF in at the top and leaves SF alone, while
+        // OF says whether the top two bits now differ.
+        assert_eq!(simulator.registers.read(Register::SI), 0x8001);
+        assert_eq!(simulator.flags.to_string(), "PSO");
+    }
+
+    #[test]
+    fn logical_operations_clear_carry_and_overflow() {
+        // Arrange
+        let mut simulator = Simulator::new();
+        simulator.flags = Flags::CARRY | Flags::OVERFLOW | Flags::AUX_CARRY;
+        simulator.registers.write(Register::AX, 0x8421);
+        simulator.registers.write(Register::BX, 0x0ff0);
+        // and ax, bx ; test ax, 0x8000
+        let and = Instruction::try_from(&[0x21, 0xd8][..]).unwrap();
+        let test = Instruction::try_from(&[0xa9, 0x00, 0x80][..]).unwrap();
+        // Act
+        simulator.execute(&and).unwrap();
+        let after_and = simulator.flags.to_string();
+        simulator.execute(&test).unwrap();
+        // Assert
+        assert_eq!(after_and, "");
+        assert_eq!(simulator.registers.read(Register::AX), 0x0420);
+        assert_eq!(simulator.flags.to_string(), "PZ");
+    }
+
+    #[test]
+    fn decodes_multiplies_divides_and_adjustments() {
+        // Arrange
+        let input = [
+            0xf7, 0xe3, 0xf6, 0x2f, 0xf7, 0x76, 0x02, 0xf6, 0xf9, 0x37, 0x3f, 0xd4, 0x0a, 0xd5,
+            0x0a, 0x27, 0x2f, 0x98, 0x99,
+        ];
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        let expected = "bits 16\nmul bx\nimul byte [bx]\ndiv word [bp + 2]\nidiv cl\n\
+            aaa\naas\naam\naad\ndaa\ndas\ncbw\ncwd";
+        assert_eq!(actual, expected);
+        assert_eq!(assembler::assemble(&actual).unwrap(), input);
+    }
+
+    #[test]
+    fn multiplies_and_divides_use_the_accumulator_pairs() {
+        // Arrange
+        let mut simulator = Simulator::new();
+        simulator.registers.write(Register::AX, 0x1234);
+        simulator.registers.write(Register::BX, 0x100);
+        simulator.registers.write(Register::CL, 0xfd);
+        // mul bx ; idiv cl ; cbw ; cwd
+        let mul = Instruction::try_from(&[0xf7, 0xe3][..]).unwrap();
+        let idiv = Instruction::try_from(&[0xf6, 0xf9][..]).unwrap();
+        let cbw = Instruction::try_from(&[0x98][..]).unwrap();
+        let cwd = Instruction::try_from(&[0x99][..]).unwrap();
+        // Act
+        simulator.execute(&mul).unwrap();
+        let product = (
+            simulator.registers.read(Register::DX),
+            simulator.registers.read(Register::AX),
+            simulator.flags.to_string(),
+        );
+        simulator.registers.write(Register::AX, -100i16 as u16);
+        simulator.execute(&idiv).unwrap();
+        let quotient = simulator.registers.read(Register::AX);
+        simulator.execute(&cbw).unwrap();
+        simulator.execute(&cwd).unwrap();
+        // Assert
+        assert_eq!(product, (0x12, 0x3400, "CO".to_string()));
+        // -100 / -3 is 33 remainder -1, in al and ah.
+        assert_eq!(quotient, 0xff21);
+        assert_eq!(simulator.registers.read(Register::AX), 0x21);
+        assert_eq!(simulator.registers.read(Register::DX), 0);
+        let timing = clocks::estimate(&mul, &simulator::Outcome::default());
+        assert_eq!((timing.clocks(), timing.max_clocks()), (118, 133));
+    }
+
+    #[test]
+    fn xchg_and_push_clocks_follow_sim86() {
+        // Arrange
+        // xchg ax, cx; xchg cx, dx; push word [bx + si + 4]
+        let input = [0x91, 0x87, 0xca, 0xff, 0x70, 0x04];
+        let xchg_accumulator = Instruction::try_from(&input[..1]).unwrap();
+        let xchg = Instruction::try_from(&input[1..3]).unwrap();
+        let push = Instruction::try_from(&input[3..]).unwrap();
+        let outcome = simulator::Outcome::default();
+        // Act
+        let timings = [&xchg_accumulator, &xchg, &push].map(|i| clocks::estimate(i, &outcome));
+        // Assert
+        assert_eq!(timings.map(|timing| timing.clocks()), [4, 4, 16]);
+        assert_eq!(timings[2].transfers, 2);
+    }
+
+    #[test]
+    fn division_errors_raise_interrupt_zero() {
+        // Arrange
+        let mut simulator = Simulator::new();
+        simulator.registers.write(Register::SP, 0x100);
+        simulator.registers.write(Register::CS, 0x50);
+        simulator.flags = Flags::INTERRUPT | Flags::ZERO;
+        // The handler for interrupt 0 lives at 2000:0010.
+        simulator.memory.load(0, &[0x10, 0x00, 0x00, 0x20]);
+        simulator.memory.load(0x500, &[0xf6, 0xf3, 0xf6, 0xf3]);
+        simulator.registers.write(Register::AX, 0x0400);
+        simulator.registers.write(Register::BL, 4);
+        // Act
+        // 0x400 / 4 doesn't fit in al, and the second div gets a zero divisor.
+        simulator.step().unwrap();
+        let overflow = (simulator.registers.read(Register::CS), simulator.ip);
+        simulator.registers.write(Register::CS, 0x50);
+        simulator.ip = 2;
+        simulator.registers.write(Register::BL, 0);
+        simulator.step().unwrap();
+        // Assert
+        assert_eq!(overflow, (0x2000, 0x10));
+        assert_eq!(
+            (simulator.registers.read(Register::CS), simulator.ip),
+            (0x2000, 0x10)
+        );
+        assert_eq!(simulator.registers.read(Register::AX), 0x0400);
+        assert_eq!(simulator.flags.to_string(), "Z");
+        // Flags, cs and the address past the div, for each of the two interrupts.
+        let stack = simulator.memory.read_range(0xf4, 12);
+        assert_eq!(stack, [4, 0, 0x50, 0, 0x40, 0, 2, 0, 0x50, 0, 0x40, 2]);
+    }
+
+    #[test]
+    fn decodes_interrupts_and_processor_control() {
+        // Arrange
+        let input = [
+            0xcd, 0x0d, 0xcc, 0xcd, 0x03, 0xce, 0xcf, 0xfa, 0xfb, 0x9b, 0xf4, 0xf0, 0x2e, 0xf6,
+            0x96, 0xb1, 0x26,
+        ];
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        let expected = "bits 16\nint 13\nint3\nint 3\ninto\niret\ncli\nsti\nwait\nhlt\n\
+            lock not byte cs:[bp + 9905]";
+        assert_eq!(actual, expected);
+        assert_eq!(assembler::assemble(&actual).unwrap(), input);
+    }
+
+    #[test]
+    fn software_interrupts_go_through_the_vector_table() {
+        // Arrange
+        // The short jmp puts the handler at offset 2, and vector 13 points there.
+        let source = "\
+            jmp start
+        handler:
+            add ax, 41
+            iret
+        start:
+            mov word [52], 2
+            mov sp, 0x100
+            mov ax, 1
+            sti
+            int 13
+            mov bx, ax
+            into
+        ";
+        let input = assembler::assemble(source).unwrap();
+        let options = SimulateOptions {
+            clocks: Some(Cpu::I8086),
+            ..SimulateOptions::default()
+        };
+        // Act
+        let (trace, simulator) = run(&input, "interrupts", options).unwrap();
+        // Assert
+        assert_eq!(simulator.registers.read(Register::BX), 42);
+        assert_eq!(simulator.registers.read(Register::SP), 0x100);
+        assert_eq!(simulator.flags.to_string(), "I");
+        assert!(trace.contains("int 13 ; Clocks: +51 = "));
+        assert!(trace.contains("into  ; Clocks: +[4,53] = [126,175] "));
+    }
+
+    #[test]
+    fn external_interrupts_wait_for_the_interrupt_flag() {
+        // Arrange
+        let mut simulator = Simulator::new();
+        simulator.registers.write(Register::SP, 0x100);
+        // sti ; hlt ; hlt, with vector 8 pointing at an iret.
+        simulator.memory.load(0, &[0xfb, 0xf4, 0xf4]);
+        simulator.memory.load(8 * 4, &[0x00, 0x02, 0x00, 0x00]);
+        simulator.memory.load(0x200, &[0xcf]);
+        simulator.request_interrupt(8);
+        // Act
+        simulator.step().unwrap();
+        let after_sti = simulator.ip;
+        // sti holds interrupts off for one more instruction.
+        simulator.step().unwrap();
+        let halted = (simulator.ip, simulator.halted);
+        let (resumed, _) = simulator.step().unwrap();
+        simulator.step().unwrap();
+        let stuck = simulator.step();
+        // Assert
+        assert_eq!(after_sti, 1);
+        assert_eq!(halted, (2, true));
+        assert_eq!(resumed.opcode, Opcode::Iret);
+        assert_eq!(simulator.registers.read(Register::SP), 0x100);
+        assert!(matches!(stuck, Err(SimulationError::Halted)));
+    }
+
+    #[test]
+    fn decodes_port_input_and_output() {
+        // Arrange
+        let input = [
+            0xe4, 0xc8, 0xec, 0xed, 0xe5, 0x60, 0xe7, 0x2c, 0xee, 0xe6, 0xff,
+        ];
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        let expected = "bits 16\nin al, 200\nin al, dx\nin ax, dx\nin ax, 96\nout 44, ax\n\
+            out dx, al\nout 255, al";
+        assert_eq!(actual, expected);
+        assert_eq!(assembler::assemble(&actual).unwrap(), input);
+    }
+
+    /// Answers every read with the port number and remembers every write.
+    #[derive(Debug, Default)]
+    struct Recorder {
+        writes: Vec<(u16, u8)>,
+    }
+
+    impl io::PortDevice for Recorder {
+        fn read(&mut self, port: u16) -> u8 {
+            port as u8
+        }
+
+        fn write(&mut self, port: u16, value: u8) {
+            self.writes.push((port, value));
+        }
+    }
+
+    #[test]
+    fn in_and_out_reach_attached_devices() {
+        // Arrange
+        let recorder = Rc::new(RefCell::new(Recorder::default()));
+        let mut simulator = Simulator::new();
+        simulator.ports.attach(0x40..=0x43, recorder.clone());
+        simulator.registers.write(Register::DX, 0x42);
+        simulator.registers.write(Register::BX, 0x1234);
+        let source = "in ax, dx\nmov ax, bx\nout 0x41, ax\nmov cx, ax\nin al, 0x80";
+        let input = assembler::assemble(source).unwrap();
+        simulator.load(&input);
+        // Act
+        simulator.step().unwrap();
+        let word = simulator.registers.read(Register::AX);
+        for _ in 0..4 {
+            simulator.step().unwrap();
+        }
+        // Assert
+        // A word is two byte accesses, the low byte first.
+        assert_eq!(word, 0x4342);
+        assert_eq!(recorder.borrow().writes, [(0x41, 0x34), (0x42, 0x12)]);
+        // Nothing answers port 0x80, so the bus floats high.
+        assert_eq!(simulator.registers.read(Register::AX), 0x12ff);
+    }
+
+    /// Requests interrupt 8 every third instruction, like a fast running timer.
+    #[derive(Debug, Default)]
+    struct Timer {
+        polls: u32,
+    }
+
+    impl io::PortDevice for Timer {
+        fn read(&mut self, _port: u16) -> u8 {
+            0
+        }
+
+        fn write(&mut self, _port: u16, _value: u8) {}
+
+        fn poll(&mut self) -> Option<u8> {
+            self.polls += 1;
+            self.polls.is_multiple_of(3).then_some(8)
+        }
+    }
+
+    #[test]
+    fn devices_can_request_interrupts() {
+        // Arrange
+        // The short jmp puts the handler at offset 2, and vector 8 points there.
+        let source = "\
+            jmp start
+        handler:
+            add bx, 1
+            iret
+        start:
+            mov word [32], 2
+            mov sp, 0x100
+            sti
+            hlt
+            hlt
+            cli
+            hlt
+        ";
+        let mut simulator = Simulator::new();
+        simulator
+            .ports
+            .attach(0x40..=0x43, Rc::new(RefCell::new(Timer::default())));
+        simulator.load(&assembler::assemble(source).unwrap());
+        // Act
+        let mut steps = 0;
+        while simulator.step().is_ok() {
+            steps += 1;
+        }
+        // Assert
+        // The first two hlts each wait for a tick to run the handler. With interrupts
+        // off, nothing wakes the last one.
+        assert_eq!(simulator.registers.read(Register::BX), 2);
+        assert_eq!(steps, 12);
+        assert!(simulator.halted);
+    }
+
+    #[test]
+    fn decodes_exchanges_loads_and_flag_transfers() {
+        // Arrange
+        let input = [
+            0x93, 0x86, 0x0f, 0xf0, 0x87, 0x07, 0xd7, 0x8d, 0x73, 0x04, 0xc5, 0x3f, 0xc4, 0x47,
+            0x04, 0x9f, 0x9e, 0xff, 0x07, 0xfe, 0xc9, 0x42, 0x4c, 0xf7, 0xd8, 0xd9, 0x07, 0xf8,
+            0xf5, 0xf9,
+        ];
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        let expected = "bits 16\nxchg ax, bx\nxchg cl, [bx]\nlock xchg [bx], ax\nxlat\n\
+            lea si, [bp + di + 4]\nlds di, [bx]\nles ax, [bx + 4]\nlahf\nsahf\n\
+            inc word [bx]\ndec cl\ninc dx\ndec sp\nneg ax\nesc 8, [bx]\nclc\ncmc\nstc";
+        assert_eq!(actual, expected);
+        assert_eq!(assembler::assemble(&actual).unwrap(), input);
+    }
+
+    #[test]
+    fn lock_xchg_between_registers_keeps_its_encoding() {
+        // Arrange
+        // lock xchg bp, dx ; lock xchg [bx], ax
+        let input = [0xf0, 0x87, 0xea, 0xf0, 0x87, 0x07];
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        assert_eq!(actual, "bits 16\nlock xchg bp, dx\nlock xchg [bx], ax");
+        assert_eq!(assembler::assemble(&actual).unwrap(), input);
+        let register = Instruction::try_from(&input[..3]).unwrap();
+        assert_eq!(text::instruction_text(&register), "lock xchg dx, bp");
+    }
+
+    #[test]
+    fn exchanges_loads_and_flag_transfers_execute() {
+        // Arrange
+        let source = "\
+            mov bx, 0x100
+            mov byte [0x105], 0x42
+            mov al, 5
+            xlat
+            lea si, [bx + 6]
+            mov word [si], 0x1234
+            mov word [si + 2], 0x5678
+            mov cx, 1
+            neg cx
+            inc cx
+            lahf
+            xchg ah, cl
+            xchg [si], dx
+            dec word [si + 2]
+            inc byte [si]
+            les di, [si]
+            hlt
+        ";
+        let mut simulator = Simulator::new();
+        simulator.load(&assembler::assemble(source).unwrap());
+        // Act
+        while simulator.step().is_ok() {}
+        // Assert
+        assert_eq!(simulator.registers.read(Register::AX), 0x42);
+        assert_eq!(simulator.registers.read(Register::SI), 0x106);
+        // lahf saw the carry neg left behind, kept by inc, along with P, A and Z.
+        assert_eq!(simulator.registers.read(Register::CX), 0x55);
+        assert_eq!(simulator.registers.read(Register::DX), 0x1234);
+        assert_eq!(simulator.registers.read(Register::DI), 1);
+        assert_eq!(simulator.registers.read(Register::ES), 0x5677);
+        assert_eq!(simulator.flags.to_string(), "C");
+    }
+
+    #[test]
+    fn sahf_and_the_carry_instructions_only_touch_their_flags() {
+        // Arrange
+        let mut simulator = Simulator::new();
+        simulator.flags = Flags::OVERFLOW | Flags::DIRECTION;
+        simulator.registers.write(Register::AH, 0xff);
+        // sahf ; cmc ; stc ; clc
+        let sahf = Instruction::try_from(&[0x9e][..]).unwrap();
+        let cmc = Instruction::try_from(&[0xf5][..]).unwrap();
+        let stc = Instruction::try_from(&[0xf9][..]).unwrap();
+        let clc = Instruction::try_from(&[0xf8][..]).unwrap();
+        // Act
+        simulator.execute(&sahf).unwrap();
+        let stored = simulator.flags.to_string();
+        simulator.execute(&cmc).unwrap();
+        let complemented = simulator.flags.to_string();
+        simulator.execute(&stc).unwrap();
+        let set = simulator.flags.to_string();
+        simulator.execute(&clc).unwrap();
+        // Assert
+        assert_eq!(stored, "CPAZSDO");
+        assert_eq!(complemented, "PAZSDO");
+        assert_eq!(set, "CPAZSDO");
+        assert_eq!(simulator.flags.to_string(), "PAZSDO");
+    }
+
+    #[test]
+    fn bp_addresses_default_to_the_stack_segment() {
+        // Arrange
+        let mut simulator = Simulator::new();
+        simulator.registers.write(Register::SS, 0x1000);
+        simulator.registers.write(Register::DS, 0x2000);
+        simulator.registers.write(Register::BP, 0x10);
+        simulator.registers.write(Register::BX, 0x10);
+        simulator.registers.write(Register::AX, 0x1234);
+        // mov [bp + 0], ax ; mov [bx], ax
+        let via_bp = Instruction::try_from(&[0x89, 0x46, 0x00][..]).unwrap();
+        let via_bx = Instruction::try_from(&[0x89, 0x07][..]).unwrap();
+        // Act
+        simulator.execute(&via_bp).unwrap();
+        simulator.execute(&via_bx).unwrap();
+        // Assert
+        let bytes = simulator.memory.as_bytes();
+        assert_eq!(bytes[0x10010..0x10012], [0x34, 0x12]);
+        assert_eq!(bytes[0x20010..0x20012], [0x34, 0x12]);
+    }
+
+    #[test]
+    fn word_accesses_wrap_within_the_segment() {
+        // Arrange
+        let mut memory = memory::Memory::default();
+        // Act
+        memory.write(0xffff, 0xffff, 0xbeef, true);
+        // Assert
+        assert_eq!(memory.read_byte(0x0ffef), 0xef);
+        assert_eq!(memory.read_byte(0xffff0), 0xbe);
+        assert_eq!(memory.read(0xffff, 0xffff, true), 0xbeef);
+    }
+
+    #[test]
+    fn step_limit_stops_programs_that_never_finish() {
+        // Arrange
+        // `cmp ax, ax` sets ZF, then `je $+0` jumps to itself forever.
+        let input = [0x39, 0xc0, 0x74, 0xfe];
+        let options = SimulateOptions {
+            max_steps: 10,
+            ..SimulateOptions::default()
+        };
+        // Act
+        let actual = simulate(&input, "loop", options).unwrap_err();
+        // Assert
+        assert!(matches!(actual.error, SimulationError::StepLimit(10)));
+        let executed: Vec<_> = actual
+            .trace
+            .lines()
+            .filter(|line| line.contains(" ; "))
+            .collect();
+        assert_eq!(executed.len(), 10);
+        assert!(executed[9].starts_with("je $+0 ; "));
+    }
+
+    #[test]
+    fn adc_and_sbb_consume_the_carry() {
+        // Arrange
+        let mut simulator = Simulator::new();
+        simulator.flags.set(Flags::CARRY, true);
+        let adc = Instruction::try_from(&[0x14, 0x01][..]).unwrap();
+        let sbb = Instruction::try_from(&[0x1c, 0x05][..]).unwrap();
+        // Act
+        simulator.execute(&adc).unwrap();
+        simulator.execute(&sbb).unwrap();
+        // Assert
+        assert_eq!(simulator.registers.read(Register::AL), 0xfd);
+        assert_eq!(simulator.flags.to_string(), "CAS");
+    }
+
+    #[test]
+    fn eight_bit_registers_alias_their_word_register() {
+        // Arrange
+        let mut simulator = Simulator::new();
+        // Act
+        simulator.registers.write(Register::AX, 0x2222);
+        simulator.registers.write(Register::AL, 0x11);
+        simulator.registers.write(Register::BH, 0x33);
+        // Assert
+        assert_eq!(simulator.registers.read(Register::AX), 0x2211);
+        assert_eq!(simulator.registers.read(Register::AH), 0x22);
+        assert_eq!(simulator.registers.read(Register::BX), 0x3300);
+    }
+
+    #[test]
+    fn assembles_nasm_source_with_labels() {
+        // Arrange
+        let source =
+            std::fs::read_to_string("perfaware/part1/listing_0041_add_sub_cmp_jnz.asm").unwrap();
+        // Act
+        let actual = assembler::assemble(&source).unwrap();
+        // Assert
+        let expected = std::fs::read("perfaware/part1/listing_0041_add_sub_cmp_jnz").unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn assembles_nasm_source_with_expressions() {
+        // Arrange
+        let source =
+            std::fs::read_to_string("perfaware/part1/listing_0054_draw_rectangle.asm").unwrap();
+        // Act
+        let actual = assembler::assemble(&source).unwrap();
+        // Assert
+        let expected = std::fs::read("perfaware/part1/listing_0054_draw_rectangle").unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn assembler_rejects_numbers_that_overflow() {
+        // Arrange
+        let sources = [
+            "mov ax, 2147483647+1",
+            "mov ax, 65536*65536",
+            "mov ax, [bx+70000]",
+        ];
+        // Act
+        let errors: Vec<_> = sources
+            .iter()
+            .map(|source| assembler::assemble(source).unwrap_err())
+            .collect();
+        // Assert
+        assert!(errors.iter().all(|err| err.line == 1));
+        assert_eq!(
+            errors[2].msg,
+            "displacement 70000 in `[bx+70000]` doesn't fit in 16 bits"
+        );
+    }
+
+    #[test]
+    fn assembler_rejects_jump_targets_that_overflow() {
+        // Arrange
+        let sources = ["jmp $-2147483647-1", "inc ax\njmp -2147483647-1"];
+        // Act
+        let errors: Vec<_> = sources
+            .iter()
+            .map(|source| assembler::assemble(source).unwrap_err())
+            .collect();
+        // Assert
+        assert_eq!(
+            errors[0],
+            assembler::AssembleError::new(1, "jump target is out of range")
+        );
+        assert_eq!(
+            errors[1],
+            assembler::AssembleError::new(2, "jump target is out of range")
+        );
+    }
+
+    #[test]
+    fn assembler_picks_the_shortest_encoding() {
+        // Arrange
+        let source = "add ax, 5\nadd ax, 1000\nmov ax, [16]\nmov [bp], al";
+        // Act
+        let actual = assembler::assemble(source).unwrap();
+        // Assert
+        assert_eq!(
+            actual,
+            vec![0x83, 0xc0, 0x05, 0x05, 0xe8, 0x03, 0xa1, 0x10, 0x00, 0x88, 0x46, 0x00]
+        );
+    }
+
+    #[test]
+    fn lossy_mode_emits_undecodable_bytes() {
+        // Arrange
+        let input = [0x89, 0xd9, 0xf1, 0x89, 0xd9];
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Lossy).unwrap();
+        // Assert
+        assert_eq!(actual, "bits 16\nmov cx, bx\ndb 0xf1\nmov cx, bx");
+    }
+
+    #[test]
+    fn strict_mode_reports_where_decoding_failed() {
+        // Arrange
+        let input = [0x89, 0xd9, 0xf1, 0x89, 0xd9];
+        // Act
+        let err = disassemble(&input, DisassemblyMode::Strict).unwrap_err();
+        // Assert
+        assert_eq!(err.kind, ParseErrorKind::UnknownOpcode);
+        assert_eq!(err.offset, 2);
+        assert_eq!(err.bytes, vec![0xf1, 0x89, 0xd9]);
+    }
+
+    #[test]
+    fn decoder_consumes_only_the_bytes_each_instruction_needs() {
+        // Arrange
+        let input = [0x04, 0x05, 0xa1, 0x34, 0x12, 0x74, 0xfb];
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Strict).unwrap();
+        // Assert
+        assert_eq!(actual, "bits 16\nadd al, 5\nmov ax, [4660]\nje $-3");
+    }
+
+    #[test]
+    fn decodes_into_typed_operands() {
+        // Arrange
+        let input = [0xc6, 0x43, 0xfb, 0x07];
+        // Act
+        let instruction = Instruction::try_from(&input[..]).unwrap();
+        // Assert
+        assert_eq!(instruction.opcode, Opcode::Mov);
+        assert_eq!(
+            instruction.operands,
+            [
+                Some(Operand::Memory {
+                    base: Some(Register::BP),
+                    index: Some(Register::DI),
+                    disp: -5,
+                    segment: None,
+                }),
+                Some(Operand::Immediate(7)),
+            ]
+        );
+        assert_eq!(instruction.size, 4);
+        assert_eq!(instruction.to_asm(), "mov [bp + di - 5], byte 7");
+    }
+
+    #[test]
+    fn truncated_instruction_is_an_error() {
+        // Arrange
+        let input = [0x89, 0xd9, 0xb9, 0x01];
+        // Act
+        let err = disassemble(&input, DisassemblyMode::Strict).unwrap_err();
+        // Assert
+        assert_eq!(err.kind, ParseErrorKind::Truncated);
+        assert_eq!(err.offset, 2);
+        assert_eq!(err.bytes, vec![0xb9, 0x01]);
+    }
+
+    #[test]
+    fn endless_prefixes_are_an_error() {
+        // Arrange
+        let mut input = vec![0xf0; 300];
+        input.push(0x90);
+        // Act
+        let err = Instruction::try_from(&input[..]).unwrap_err();
+        // Assert
+        assert_eq!(err.kind, ParseErrorKind::TooLong);
+    }
+
+    #[test]
+    fn the_simulator_fetches_as_many_prefixes_as_the_decoder_takes() {
+        // Arrange
+        // A segment override and a word move to memory with a displacement and data.
+        let longest = |prefixes| {
+            let mut input = vec![0x26; prefixes];
+            input.extend([0xc7, 0x87, 0x34, 0x12, 0x01, 0x00]);
+            input
+        };
+        let mut simulator = Simulator::new();
+        simulator.load(&longest(MAX_PREFIXES));
+        let mut too_long = Simulator::new();
+        too_long.load(&longest(MAX_PREFIXES + 1));
+        // Act
+        let fetched = simulator.fetch().unwrap();
+        let err = too_long.fetch().unwrap_err();
+        // Assert
+        assert_eq!(
+            usize::from(fetched.bytes()),
+            MAX_PREFIXES + MAX_INSTRUCTION_BYTES
+        );
+        assert!(matches!(
+            err,
+            SimulationError::Decode(err) if err.kind == ParseErrorKind::TooLong
+        ));
+    }
+
+    #[test]
+    fn lossy_disassembly_of_a_long_prefix_run_stays_linear() {
+        // Arrange
+        let input = vec![0x26; 200_000];
+        let start = std::time::Instant::now();
+        // Act
+        let actual = disassemble(&input, DisassemblyMode::Lossy).unwrap();
+        // Assert
+        assert_eq!(
+            actual.lines().filter(|line| *line == "db 0x26").count(),
+            200_000
+        );
+        // Rescanning the run at every offset took minutes for this many bytes.
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+    }
+}
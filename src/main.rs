@@ -1,135 +1,214 @@
-#![feature(unchecked_math)]
-#![allow(dead_code, unused)]
-mod mode;
-mod register;
-mod instruction;
-
-use crate::mode::Mode;
-use crate::register::Register;
-use crate::instruction::Instruction;
-
-use std::fmt::Display;
+use computer_enhance::clocks::Cpu;
+use computer_enhance::image::ImageFormat;
+use computer_enhance::{disassemble, export_image, run, DisassemblyMode, SimulateOptions};
+
+const USAGE: &str = "\
+usage: computer_enhance <COMMAND> [OPTIONS] BINARY
+
+commands:
+  disasm [--lossy]                  print BINARY as nasm source
+  exec [--stop-on-ret] [--max-steps N]
+                                    execute BINARY and print the trace
+  cycles [--8088] [--stop-on-ret] [--max-steps N]
+                                    like exec, with clock estimates on every line
+  dump [--stop-on-ret] [--max-steps N] [--out FILE]
+       [--image FILE [--image-address N] [--width N] [--height N]]
+                                    execute BINARY and write its memory to FILE, or
+                                    render part of it as a .png or .ppm image
+
+--max-steps gives up on a program that is still running after N instructions
+(default 1000000); exec and cycles still print the trace up to that point.";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Command {
+    Disasm,
+    Exec,
+    Cycles,
+    Dump,
+}
 
-use bitvec::prelude::*;
+/// Command line options. Each command reads only the ones listed for it in
+/// [`USAGE`].
+#[derive(Debug)]
+struct Args {
+    command: Command,
+    path: String,
+    mode: DisassemblyMode,
+    cpu: Cpu,
+    stop_on_ret: bool,
+    max_steps: usize,
+    out: Option<String>,
+    image: Option<String>,
+    image_address: u32,
+    width: usize,
+    height: usize,
+}
 
-pub fn disassemble(input: &BitSlice<u8, Msb0>, signed_output: bool) -> String {
-    let mut strs: Vec<String> = vec!["bits 16".to_string()];
-    let mut bit_ptr = 0;
-    while bit_ptr < input.len() {
-        let end = if input[bit_ptr..].len() >= 48 {
-            48
-        } else if input[bit_ptr..].len() >= 40 {
-            40
-        } else if input[bit_ptr..].len() >= 32 {
-            32
-        } else if input[bit_ptr..].len() >= 24 {
-            24
-        } else {
-            16
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let command = match args.next().as_deref() {
+            Some("disasm") => Command::Disasm,
+            Some("exec") => Command::Exec,
+            Some("cycles") => Command::Cycles,
+            Some("dump") => Command::Dump,
+            Some(other) => return Err(format!("unknown command {}", other)),
+            None => return Err("missing command".to_string()),
         };
-        let current = &input[bit_ptr..bit_ptr + end];
-        let instruction = Instruction::try_from(current).unwrap();
+        let mut path = None;
+        let mut parsed = Args {
+            command,
+            path: String::new(),
+            mode: DisassemblyMode::Strict,
+            cpu: Cpu::I8086,
+            stop_on_ret: false,
+            max_steps: SimulateOptions::default().max_steps,
+            out: None,
+            image: None,
+            // Where the draw_rectangle listings put their pixels.
+            image_address: 256,
+            width: 64,
+            height: 64,
+        };
+        while let Some(arg) = args.next() {
+            match (command, arg.as_str()) {
+                (Command::Disasm, "--lossy") => parsed.mode = DisassemblyMode::Lossy,
+                (Command::Cycles, "--8088") => parsed.cpu = Cpu::I8088,
+                (Command::Exec | Command::Cycles | Command::Dump, "--stop-on-ret") => {
+                    parsed.stop_on_ret = true
+                }
+                (Command::Exec | Command::Cycles | Command::Dump, "--max-steps") => {
+                    parsed.max_steps = number(&arg, args.next())?
+                }
+                (Command::Dump, "--out") => {
+                    parsed.out = Some(args.next().ok_or("--out needs a file")?)
+                }
+                (Command::Dump, "--image") => {
+                    parsed.image = Some(args.next().ok_or("--image needs a file")?)
+                }
+                (Command::Dump, "--image-address") => {
+                    parsed.image_address = number(&arg, args.next())?
+                }
+                (Command::Dump, "--width") => parsed.width = number(&arg, args.next())?,
+                (Command::Dump, "--height") => parsed.height = number(&arg, args.next())?,
+                (_, flag) if flag.starts_with("--") => {
+                    return Err(format!("unknown option {}", flag))
+                }
+                _ if path.is_none() => path = Some(arg),
+                _ => return Err(format!("unexpected argument {}", arg)),
+            }
+        }
+        parsed.path = path.ok_or("missing BINARY")?;
+        if command == Command::Dump && parsed.out.is_none() && parsed.image.is_none() {
+            return Err("dump needs --out or --image".to_string());
+        }
+        Ok(parsed)
+    }
+}
 
-        let asm = instruction.to_asm();
-        println!("{}", asm);
-        strs.push(asm);
+fn number<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    value
+        .and_then(|value| value.parse().ok())
+        .ok_or(format!("{} needs a number", flag))
+}
 
-        bit_ptr += instruction.bytes() as usize * 8;
+fn main() {
+    let args = Args::parse(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{err}\n{USAGE}");
+        std::process::exit(2);
+    });
+    if let Err(err) = run_cli(&args) {
+        eprintln!("{err}");
+        std::process::exit(1);
     }
-    strs.join("\n")
 }
 
-fn main() {
-    let input = std::fs::read("perfaware/part1/listing_0039_more_movs").unwrap();
-    let bits = input.view_bits::<Msb0>();
-    let output = disassemble(bits, false);
-    println!("{output}");
+fn run_cli(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let input = std::fs::read(&args.path)?;
+    if args.command == Command::Disasm {
+        println!("{}", disassemble(&input, args.mode)?);
+        return Ok(());
+    }
+
+    let options = SimulateOptions {
+        clocks: (args.command == Command::Cycles).then_some(args.cpu),
+        stop_on_ret: args.stop_on_ret,
+        max_steps: args.max_steps,
+        ..SimulateOptions::default()
+    };
+    let print_trace = args.command != Command::Dump;
+    let (trace, simulator) = match run(&input, &args.path, options) {
+        Ok(finished) => finished,
+        Err(err) => {
+            if print_trace {
+                print!("{}", err.trace);
+            }
+            return Err(err.into());
+        }
+    };
+    if print_trace {
+        print!("{trace}");
+    }
+    if let Some(path) = &args.out {
+        std::fs::write(path, simulator.memory.as_bytes())?;
+    }
+    if let Some(path) = &args.image {
+        let format = std::path::Path::new(path)
+            .extension()
+            .and_then(|extension| ImageFormat::from_extension(&extension.to_string_lossy()))
+            .ok_or("--image has to end in .png or .ppm")?;
+        let image = export_image(
+            &simulator,
+            format,
+            args.image_address,
+            args.width,
+            args.height,
+        )?;
+        std::fs::write(path, image)?;
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn skip_preamble(s: &str) -> String {
-        let contents = std::fs::read_to_string(s).unwrap();
-
-        contents
-            .lines()
-            .filter(|l| !l.is_empty() && !l.starts_with("bits") && !l.starts_with(";"))
-            .collect::<Vec<&str>>()
-            .join("\n")
-    }
-
-    fn compare(actual: &str, listing: &str, expected_bin_path: &str) {
-        let actual_asm_path = format!("tmp/{}_actual.asm", listing);
-        let actual_bin_path = format!("tmp/{}_actual", listing);
-        std::fs::write(&actual_asm_path, &actual);
-        std::process::Command::new("nasm")
-            .arg(&actual_asm_path)
-            .output()
-            .unwrap();
-        let actual_contents = std::fs::read(actual_bin_path).unwrap();
-        let expected_contents = std::fs::read(expected_bin_path).unwrap();
-        let bs1 = actual_contents[1].view_bits::<Msb0>();
-        let bs2 = expected_contents[1].view_bits::<Msb0>();
-        assert_eq!(actual_contents, expected_contents, "actual: {}, expected: {}", bs1, bs2);
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
     }
 
     #[test]
-    fn correctly_handles_single_register_mov() {
+    fn parses_a_command_its_flags_and_the_binary() {
         // Arrange
-        let input = std::fs::read("perfaware/part1/listing_0037_single_register_mov").unwrap();
-        let bits = input.view_bits::<Msb0>();
+        let args = [
+            "cycles",
+            "--8088",
+            "listing",
+            "--stop-on-ret",
+            "--max-steps",
+            "50",
+        ];
         // Act
-        let actual = disassemble(bits, false);
+        let parsed = parse(&args).unwrap();
         // Assert
-        compare(
-            &actual,
-            "0037",
-            "perfaware/part1/listing_0037_single_register_mov",
-        )
+        assert_eq!(parsed.command, Command::Cycles);
+        assert_eq!(parsed.path, "listing");
+        assert_eq!(parsed.cpu, Cpu::I8088);
+        assert!(parsed.stop_on_ret);
+        assert_eq!(parsed.max_steps, 50);
     }
 
     #[test]
-    fn correctly_handles_many_register_mov() {
+    fn rejects_flags_that_belong_to_another_command() {
         // Arrange
-        let input = std::fs::read("perfaware/part1/listing_0038_many_register_mov").unwrap();
-        let bits = input.view_bits::<Msb0>();
+        let args = ["disasm", "--8088", "listing"];
         // Act
-        let actual = disassemble(bits, false);
+        let err = parse(&args).unwrap_err();
         // Assert
-        compare(
-            &actual,
-            "0038",
-            "perfaware/part1/listing_0038_many_register_mov",
-        )
+        assert_eq!(err, "unknown option --8088");
+        assert_eq!(
+            parse(&["dump", "listing"]).unwrap_err(),
+            "dump needs --out or --image"
+        );
+        assert_eq!(parse(&["exec"]).unwrap_err(), "missing BINARY");
     }
-
-    #[test]
-    fn correctly_handles_more_movs() {
-        // Arrange
-        let input = std::fs::read("perfaware/part1/listing_0039_more_movs").unwrap();
-        let bits = input.view_bits::<Msb0>();
-        // Act
-        let actual = disassemble(bits, false);
-        // Assert
-        compare(&actual, "0039", "perfaware/part1/listing_0039_more_movs")
-    }
-
-    // #[test]
-    // fn correctly_handles_more_movs_challenge() {
-    //     // Arrange
-    //     let binary_file = "perfaware/part1/listing_0040_challenge_movs";
-    //     let input = std::fs::read(binary_file).unwrap();
-    //     let bits = input.view_bits::<Msb0>();
-    //     // Act
-    //     let actual = disassemble(bits, false);
-    //     // Assert
-    //     compare(
-    //         &actual,
-    //         "0040",
-    //         "perfaware/part1/listing_0040_challenge_movs",
-    //     )
-    // }
-
 }
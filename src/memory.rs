@@ -0,0 +1,72 @@
+//! The 8086's 1 MiB address space. Addresses are formed from a segment and an offset
+//! the way the hardware does it: `segment * 16 + offset`, wrapping at 1 MiB.
+
+pub const MEMORY_SIZE: usize = 1 << 20;
+
+/// The physical address `segment:offset` refers to.
+pub fn physical(segment: u16, offset: u16) -> u32 {
+    (((segment as u32) << 4) + offset as u32) & (MEMORY_SIZE as u32 - 1)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Memory {
+    bytes: Box<[u8]>,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self {
+            bytes: vec![0; MEMORY_SIZE].into_boxed_slice(),
+        }
+    }
+}
+
+impl Memory {
+    pub fn read_byte(&self, address: u32) -> u8 {
+        self.bytes[address as usize % MEMORY_SIZE]
+    }
+
+    pub fn write_byte(&mut self, address: u32, value: u8) {
+        self.bytes[address as usize % MEMORY_SIZE] = value;
+    }
+
+    /// Reads a byte, or a little-endian word when `wide` is set. The second byte of a
+    /// word at offset `0xffff` comes from offset 0 of the same segment, as on an 8086.
+    pub fn read(&self, segment: u16, offset: u16, wide: bool) -> u16 {
+        let lo = self.read_byte(physical(segment, offset)) as u16;
+        if !wide {
+            return lo;
+        }
+        let hi = self.read_byte(physical(segment, offset.wrapping_add(1))) as u16;
+        lo | (hi << 8)
+    }
+
+    /// Writes the low byte of `value`, or all of it little-endian when `wide` is set.
+    pub fn write(&mut self, segment: u16, offset: u16, value: u16, wide: bool) {
+        self.write_byte(physical(segment, offset), value as u8);
+        if wide {
+            self.write_byte(
+                physical(segment, offset.wrapping_add(1)),
+                (value >> 8) as u8,
+            );
+        }
+    }
+
+    /// Copies `bytes` in starting at `address`, wrapping around the end of memory.
+    pub fn load(&mut self, address: u32, bytes: &[u8]) {
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.write_byte(address.wrapping_add(i as u32), byte);
+        }
+    }
+
+    /// Copies out `len` bytes starting at `address`, wrapping around the end of memory.
+    pub fn read_range(&self, address: u32, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| self.read_byte(address.wrapping_add(i as u32)))
+            .collect()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
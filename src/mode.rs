@@ -6,13 +6,13 @@ pub enum Mode {
     Register,
 }
 
-impl<'a> From<&'a [bool; 2]> for Mode {
-    fn from(bits: &'a [bool; 2]) -> Self {
-        match *bits {
-            [true, true] => Mode::Register,
-            [true, false] => Mode::Displace16Bits,
-            [false, true] => Mode::Displace8Bits,
-            [false, false] => Mode::Memory,
+impl From<u8> for Mode {
+    fn from(bits: u8) -> Self {
+        match bits & 0b11 {
+            0b11 => Mode::Register,
+            0b10 => Mode::Displace16Bits,
+            0b01 => Mode::Displace8Bits,
+            _ => Mode::Memory,
         }
     }
 }
@@ -0,0 +1,220 @@
+use std::fmt::Display;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Opcode {
+    Mov,
+    Push,
+    Pop,
+    Pushf,
+    Popf,
+    In,
+    Out,
+    Xchg,
+    Xlat,
+    Lea,
+    Lds,
+    Les,
+    Lahf,
+    Sahf,
+
+    Add,
+    Adc,
+    Sub,
+    Sbb,
+    Cmp,
+    Inc,
+    Dec,
+    Neg,
+    Mul,
+    Imul,
+    Div,
+    Idiv,
+    Aaa,
+    Aas,
+    Aam,
+    Aad,
+    Daa,
+    Das,
+    Cbw,
+    Cwd,
+
+    And,
+    Test,
+    Or,
+    Xor,
+    Not,
+    Shl,
+    Shr,
+    Sar,
+    Rol,
+    Ror,
+    Rcl,
+    Rcr,
+
+    Jo,
+    Jno,
+    Jb,
+    Jnb,
+    Je,
+    Jne,
+    Jbe,
+    Ja,
+    Js,
+    Jns,
+    Jp,
+    Jnp,
+    Jl,
+    Jnl,
+    Jle,
+    Jg,
+    Loopnz,
+    Loopz,
+    Loop,
+    Jcxz,
+
+    Call,
+    Jmp,
+    Ret,
+    Retf,
+
+    Movs,
+    Cmps,
+    Scas,
+    Lods,
+    Stos,
+    Cld,
+    Std,
+
+    Int,
+    Int3,
+    Into,
+    Iret,
+    Cli,
+    Sti,
+    Hlt,
+    Wait,
+    Clc,
+    Cmc,
+    Stc,
+    Esc,
+}
+
+impl Opcode {
+    /// The shifts and rotates, whose second operand is a count rather than a value of
+    /// the operand's size.
+    pub fn is_shift(&self) -> bool {
+        matches!(
+            self,
+            Self::Shl | Self::Shr | Self::Sar | Self::Rol | Self::Ror | Self::Rcl | Self::Rcr
+        )
+    }
+
+    /// The string instructions, which take their operands from si and di and can be
+    /// repeated with a rep prefix.
+    pub fn is_string(&self) -> bool {
+        matches!(
+            self,
+            Self::Movs | Self::Cmps | Self::Scas | Self::Lods | Self::Stos
+        )
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Mov => "mov",
+            Self::Push => "push",
+            Self::Pop => "pop",
+            Self::Pushf => "pushf",
+            Self::Popf => "popf",
+            Self::In => "in",
+            Self::Out => "out",
+            Self::Xchg => "xchg",
+            Self::Xlat => "xlat",
+            Self::Lea => "lea",
+            Self::Lds => "lds",
+            Self::Les => "les",
+            Self::Lahf => "lahf",
+            Self::Sahf => "sahf",
+            Self::Add => "add",
+            Self::Adc => "adc",
+            Self::Sub => "sub",
+            Self::Sbb => "sbb",
+            Self::Cmp => "cmp",
+            Self::Inc => "inc",
+            Self::Dec => "dec",
+            Self::Neg => "neg",
+            Self::Mul => "mul",
+            Self::Imul => "imul",
+            Self::Div => "div",
+            Self::Idiv => "idiv",
+            Self::Aaa => "aaa",
+            Self::Aas => "aas",
+            Self::Aam => "aam",
+            Self::Aad => "aad",
+            Self::Daa => "daa",
+            Self::Das => "das",
+            Self::Cbw => "cbw",
+            Self::Cwd => "cwd",
+            Self::And => "and",
+            Self::Test => "test",
+            Self::Or => "or",
+            Self::Xor => "xor",
+            Self::Not => "not",
+            Self::Shl => "shl",
+            Self::Shr => "shr",
+            Self::Sar => "sar",
+            Self::Rol => "rol",
+            Self::Ror => "ror",
+            Self::Rcl => "rcl",
+            Self::Rcr => "rcr",
+            Self::Jo => "jo",
+            Self::Jno => "jno",
+            Self::Jb => "jb",
+            Self::Jnb => "jnb",
+            Self::Je => "je",
+            Self::Jne => "jne",
+            Self::Jbe => "jbe",
+            Self::Ja => "ja",
+            Self::Js => "js",
+            Self::Jns => "jns",
+            Self::Jp => "jp",
+            Self::Jnp => "jnp",
+            Self::Jl => "jl",
+            Self::Jnl => "jnl",
+            Self::Jle => "jle",
+            Self::Jg => "jg",
+            Self::Loopnz => "loopnz",
+            Self::Loopz => "loopz",
+            Self::Loop => "loop",
+            Self::Jcxz => "jcxz",
+            Self::Call => "call",
+            Self::Jmp => "jmp",
+            Self::Ret => "ret",
+            Self::Retf => "retf",
+            Self::Movs => "movs",
+            Self::Cmps => "cmps",
+            Self::Scas => "scas",
+            Self::Lods => "lods",
+            Self::Stos => "stos",
+            Self::Cld => "cld",
+            Self::Std => "std",
+            Self::Int => "int",
+            Self::Int3 => "int3",
+            Self::Into => "into",
+            Self::Iret => "iret",
+            Self::Cli => "cli",
+            Self::Sti => "sti",
+            Self::Hlt => "hlt",
+            Self::Wait => "wait",
+            Self::Clc => "clc",
+            Self::Cmc => "cmc",
+            Self::Stc => "stc",
+            Self::Esc => "esc",
+        }
+    }
+}
+
+impl Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
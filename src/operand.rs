@@ -0,0 +1,56 @@
+use crate::register::Register;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Register(Register),
+    /// `[base + index + disp]`. A direct address has neither a base nor an index,
+    /// and `disp` holds the address itself.
+    Memory {
+        base: Option<Register>,
+        index: Option<Register>,
+        disp: i16,
+        segment: Option<Register>,
+    },
+    /// The data as it was encoded: sixteen bit data is zero extended, eight bit
+    /// data is sign extended only when the `s` bit asked for it.
+    Immediate(i32),
+    /// A jump displacement, relative to the end of the instruction.
+    RelativeJump(i16),
+    /// A far jump or call target, `segment:offset`.
+    Intersegment {
+        segment: u16,
+        offset: u16,
+    },
+}
+
+impl Operand {
+    /// Builds the memory operand selected by the `rm` field when `mod` isn't `11`.
+    pub fn effective_address(rm: u8, direct: bool, disp: i16) -> Self {
+        use Register::*;
+        let (base, index) = match rm & 0b111 {
+            0b000 => (Some(BX), Some(SI)),
+            0b001 => (Some(BX), Some(DI)),
+            0b010 => (Some(BP), Some(SI)),
+            0b011 => (Some(BP), Some(DI)),
+            0b100 => (Some(SI), None),
+            0b101 => (Some(DI), None),
+            0b110 if direct => (None, None),
+            0b110 => (Some(BP), None),
+            _ => (Some(BX), None),
+        };
+        Self::Memory {
+            base,
+            index,
+            disp,
+            segment: None,
+        }
+    }
+
+    pub fn is_register(&self) -> bool {
+        matches!(self, Self::Register(_))
+    }
+
+    pub fn is_memory(&self) -> bool {
+        matches!(self, Self::Memory { .. })
+    }
+}
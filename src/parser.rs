@@ -0,0 +1,369 @@
+//! Parses the subset of nasm syntax the listings in `perfaware/part1` are written in.
+
+use crate::{
+    assembler::AssembleError,
+    instruction::Repeat,
+    opcode::Opcode,
+    operand::Operand,
+    register::Register,
+    table::{self, Field},
+};
+
+/// Where a jump goes, before the addresses of labels are known.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    Label(String),
+    /// `$+N`, relative to the start of the instruction.
+    Relative(i32),
+    /// A bare number, relative to the start of the program.
+    Absolute(i32),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParsedOperand {
+    Operand(Operand),
+    Target(Target),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParsedInstruction {
+    pub line: usize,
+    pub opcode: Opcode,
+    /// Destination first.
+    pub operands: Vec<ParsedOperand>,
+    pub wide: bool,
+    pub far: bool,
+    pub lock: bool,
+    pub repeat: Option<Repeat>,
+    /// A segment register written as a prefix, e.g. `es movsb`.
+    pub segment: Option<Register>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Statement {
+    Label(String),
+    Instruction(ParsedInstruction),
+}
+
+pub fn parse(source: &str) -> Result<Vec<Statement>, AssembleError> {
+    let mut statements = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let number = index + 1;
+        let mut text = line.split(';').next().unwrap_or_default().trim();
+        if text.is_empty() || text.starts_with("bits ") {
+            continue;
+        }
+        if let Some((label, rest)) = text.split_once(':') {
+            if is_identifier(label) {
+                statements.push(Statement::Label(label.to_string()));
+                text = rest.trim();
+                if text.is_empty() {
+                    continue;
+                }
+            }
+        }
+        let instruction = parse_instruction(text).map_err(|msg| AssembleError::new(number, msg))?;
+        statements.push(Statement::Instruction(ParsedInstruction {
+            line: number,
+            ..instruction
+        }));
+    }
+    Ok(statements)
+}
+
+fn parse_instruction(text: &str) -> Result<ParsedInstruction, String> {
+    let (mut lock, mut repeat, mut segment) = (false, None, None);
+    let mut text = text;
+    let (mnemonic, rest) = loop {
+        let (word, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let word = word.to_ascii_lowercase();
+        match word.as_str() {
+            "lock" => lock = true,
+            "rep" | "repe" | "repz" => repeat = Some(Repeat::Rep),
+            "repne" | "repnz" => repeat = Some(Repeat::Repne),
+            _ => match Register::from_name(&word) {
+                Some(reg) if reg.is_segment() => segment = Some(reg),
+                _ => break (word, rest),
+            },
+        }
+        text = rest.trim_start();
+    };
+    // String instructions carry their size in the mnemonic, e.g. `movsb`.
+    let (opcode, string_size) = match lookup_opcode(&mnemonic) {
+        Some(opcode) => (opcode, None),
+        None => {
+            lookup_string_opcode(&mnemonic).ok_or(format!("unknown mnemonic `{}`", mnemonic))?
+        }
+    };
+    let is_jump = table::ENCODINGS
+        .iter()
+        .any(|encoding| encoding.opcode == opcode && encoding.fields.contains(&Field::RelJmp));
+
+    let mut operands = Vec::new();
+    let mut size = None;
+    let mut far = false;
+    for operand in rest
+        .split(',')
+        .map(str::trim)
+        .filter(|operand| !operand.is_empty())
+    {
+        let operand = match strip_keyword(operand, "far") {
+            Some(operand) => {
+                far = true;
+                operand
+            }
+            None => operand,
+        };
+        let (operand_size, operand) = strip_size(operand);
+        size = size.or(operand_size);
+        // call and jmp also take registers, memory and `segment:offset` operands.
+        let is_target = !operand.starts_with('[')
+            && !operand.contains(':')
+            && Register::from_name(operand).is_none();
+        operands.push(if is_jump && is_target {
+            ParsedOperand::Target(parse_target(operand)?)
+        } else {
+            ParsedOperand::Operand(parse_operand(operand)?)
+        });
+    }
+
+    // A `segment:offset` target is far without saying so, and so is retf.
+    let far = far
+        || opcode == Opcode::Retf
+        || operands.iter().any(|operand| {
+            matches!(
+                operand,
+                ParsedOperand::Operand(Operand::Intersegment { .. })
+            )
+        });
+    // A shift count says nothing about the size of what is shifted, and the dx an out
+    // writes to says nothing about the size of what is written.
+    let sizing = if opcode.is_shift() { 1 } else { operands.len() };
+    let port = (opcode == Opcode::Out) as usize;
+    let register_size = operands
+        .iter()
+        .take(sizing)
+        .skip(port)
+        .find_map(|operand| match operand {
+            ParsedOperand::Operand(Operand::Register(reg)) => Some(reg.is_wide()),
+            _ => None,
+        });
+    Ok(ParsedInstruction {
+        line: 0,
+        opcode,
+        operands,
+        wide: register_size.or(size).or(string_size).unwrap_or(false),
+        far,
+        lock,
+        repeat,
+        segment,
+    })
+}
+
+/// Maps nasm's alternative condition names onto the ones the decoder prints.
+fn lookup_opcode(mnemonic: &str) -> Option<Opcode> {
+    let name = match mnemonic {
+        "jz" => "je",
+        "jnz" => "jne",
+        "jc" | "jnae" => "jb",
+        "jnc" | "jae" => "jnb",
+        "jna" => "jbe",
+        "jnbe" => "ja",
+        "jpe" => "jp",
+        "jpo" => "jnp",
+        "jnge" => "jl",
+        "jge" => "jnl",
+        "jng" => "jle",
+        "jnle" => "jg",
+        "loope" => "loopz",
+        "loopne" => "loopnz",
+        "sal" => "shl",
+        name => name,
+    };
+    table::ENCODINGS
+        .iter()
+        .map(|encoding| encoding.opcode)
+        .find(|opcode| opcode.name() == name)
+}
+
+/// `movsb` and friends, along with whether they work on words.
+fn lookup_string_opcode(mnemonic: &str) -> Option<(Opcode, Option<bool>)> {
+    let wide = match mnemonic.chars().last()? {
+        'b' => false,
+        'w' => true,
+        _ => return None,
+    };
+    let opcode = lookup_opcode(&mnemonic[..mnemonic.len() - 1])?;
+    opcode.is_string().then_some((opcode, Some(wide)))
+}
+
+fn strip_size(operand: &str) -> (Option<bool>, &str) {
+    if let Some(rest) = strip_keyword(operand, "byte") {
+        (Some(false), rest)
+    } else if let Some(rest) = strip_keyword(operand, "word") {
+        (Some(true), rest)
+    } else {
+        (None, operand)
+    }
+}
+
+/// `operand` without a leading `keyword`, if it starts with one.
+fn strip_keyword<'a>(operand: &'a str, keyword: &str) -> Option<&'a str> {
+    let (first, rest) = operand.split_once(char::is_whitespace)?;
+    first
+        .eq_ignore_ascii_case(keyword)
+        .then(|| rest.trim_start())
+}
+
+fn parse_operand(text: &str) -> Result<Operand, String> {
+    // A segment override is written in front of the brackets, e.g. `es:[bp]`, while
+    // a far jump target is two numbers, e.g. `123:456`.
+    let (segment, address) = match text.split_once(':') {
+        Some((name, rest)) => match Register::from_name(name.trim()) {
+            Some(reg) if reg.is_segment() => (Some(reg), rest.trim()),
+            _ => {
+                return match (evaluate(name), evaluate(rest)) {
+                    (Some(segment), Some(offset)) => Ok(Operand::Intersegment {
+                        segment: segment as u16,
+                        offset: offset as u16,
+                    }),
+                    _ => Err(format!("`{}` isn't a segment register", name)),
+                }
+            }
+        },
+        None => (None, text),
+    };
+    if let Some(inner) = address.strip_prefix('[') {
+        let inner = inner
+            .strip_suffix(']')
+            .ok_or(format!("unterminated memory operand `{}`", text))?;
+        let mut operand = parse_memory(inner)?;
+        if let Operand::Memory { segment: slot, .. } = &mut operand {
+            *slot = segment;
+        }
+        return Ok(operand);
+    }
+    if segment.is_some() {
+        return Err(format!(
+            "a segment override needs a memory operand: `{}`",
+            text
+        ));
+    }
+    if let Some(reg) = Register::from_name(text) {
+        return Ok(Operand::Register(reg));
+    }
+    let value = evaluate(text).ok_or(format!("can't parse operand `{}`", text))?;
+    Ok(Operand::Immediate(value))
+}
+
+fn parse_memory(text: &str) -> Result<Operand, String> {
+    let mut registers = Vec::new();
+    let mut disp = 0i32;
+    for (sign, term) in split_terms(text) {
+        if let Some(reg) = Register::from_name(term) {
+            if sign < 0 {
+                return Err(format!("can't subtract register `{}`", term));
+            }
+            registers.push(reg);
+        } else {
+            disp = product(term)
+                .and_then(|value| disp.checked_add(sign * value))
+                .ok_or(format!("can't parse displacement `{}`", term))?;
+        }
+    }
+
+    // nasm accepts the terms in any order; the encodings always pair bx/bp with si/di.
+    registers.sort_by_key(|reg| !matches!(reg, Register::BX | Register::BP));
+    let (base, index) = match registers[..] {
+        [] => (None, None),
+        [base] => (Some(base), None),
+        [base, index] => (Some(base), Some(index)),
+        _ => return Err(format!("too many registers in `[{}]`", text)),
+    };
+    // Direct addresses are written unsigned, displacements from a register signed.
+    let disp = i16::try_from(disp)
+        .or_else(|_| u16::try_from(disp).map(|disp| disp as i16))
+        .map_err(|_| {
+            format!(
+                "displacement {} in `[{}]` doesn't fit in 16 bits",
+                disp, text
+            )
+        })?;
+    Ok(Operand::Memory {
+        base,
+        index,
+        disp,
+        segment: None,
+    })
+}
+
+fn parse_target(text: &str) -> Result<Target, String> {
+    if let Some(offset) = text.strip_prefix('$') {
+        let offset = if offset.trim().is_empty() {
+            Some(0)
+        } else {
+            evaluate(offset)
+        };
+        return offset
+            .map(Target::Relative)
+            .ok_or(format!("can't parse jump target `{}`", text));
+    }
+    if is_identifier(text) {
+        return Ok(Target::Label(text.to_string()));
+    }
+    evaluate(text)
+        .map(Target::Absolute)
+        .ok_or(format!("can't parse jump target `{}`", text))
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Evaluates sums and products of decimal and `0x` hexadecimal numbers, or gives
+/// up when the result doesn't fit in an i32.
+fn evaluate(text: &str) -> Option<i32> {
+    split_terms(text)
+        .into_iter()
+        .try_fold(0i32, |total, (sign, term)| {
+            total.checked_add(sign * product(term)?)
+        })
+}
+
+/// Splits `a + b - c` into its terms along with the sign in front of each.
+fn split_terms(text: &str) -> Vec<(i32, &str)> {
+    let mut terms = Vec::new();
+    let mut sign = 1;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if c == '+' || c == '-' {
+            let term = text[start..i].trim();
+            if !term.is_empty() {
+                terms.push((sign, term));
+                sign = 1;
+            }
+            if c == '-' {
+                sign = -sign;
+            }
+            start = i + 1;
+        }
+    }
+    terms.push((sign, text[start..].trim()));
+    terms
+}
+
+fn product(term: &str) -> Option<i32> {
+    term.split('*')
+        .map(|factor| number(factor.trim()))
+        .try_fold(1i32, |total, factor| total.checked_mul(factor?))
+}
+
+fn number(text: &str) -> Option<i32> {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        i32::from_str_radix(hex, 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
@@ -1,6 +1,6 @@
 use std::fmt::Display;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Register {
     AL,
     AH,
@@ -18,35 +18,80 @@ pub enum Register {
     BP,
     SI,
     DI,
+    ES,
+    CS,
+    SS,
+    DS,
 }
 
 impl Register {
-    pub fn from_bits(bits: &[bool; 3], wide: bool) -> Self {
-        match (bits[0], bits[1], bits[2], wide) {
-            (false, false, false, false) => Self::AL,
-            (false, false, false, true) => Self::AX,
-            (true, false, false, false) => Self::AH,
-
-            (true, false, false, true) => Self::SP,
-
-            (false, true, true, false) => Self::BL,
-            (false, true, true, true) => Self::BX,
-            (true, true, true, false) => Self::BH,
+    pub fn from_bits(bits: u8, wide: bool) -> Self {
+        match (bits & 0b111, wide) {
+            (0b000, false) => Self::AL,
+            (0b001, false) => Self::CL,
+            (0b010, false) => Self::DL,
+            (0b011, false) => Self::BL,
+            (0b100, false) => Self::AH,
+            (0b101, false) => Self::CH,
+            (0b110, false) => Self::DH,
+            (_, false) => Self::BH,
+            (0b000, true) => Self::AX,
+            (0b001, true) => Self::CX,
+            (0b010, true) => Self::DX,
+            (0b011, true) => Self::BX,
+            (0b100, true) => Self::SP,
+            (0b101, true) => Self::BP,
+            (0b110, true) => Self::SI,
+            (_, true) => Self::DI,
+        }
+    }
 
-            (true, true, true, true) => Self::DI,
+    /// The segment register selected by a two bit `sr` field.
+    pub fn from_segment_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0b00 => Self::ES,
+            0b01 => Self::CS,
+            0b10 => Self::SS,
+            _ => Self::DS,
+        }
+    }
 
-            (false, false, true, false) => Self::CL,
-            (false, false, true, true) => Self::CX,
-            (true, false, true, false) => Self::CH,
+    /// The three bit encoding of the register, as used by the `reg` and `rm` fields.
+    /// Segment registers use their two bit `sr` encoding.
+    pub fn index(&self) -> u8 {
+        match self {
+            Self::ES => 0b00,
+            Self::CS => 0b01,
+            Self::SS => 0b10,
+            Self::DS => 0b11,
+            Self::AL | Self::AX => 0b000,
+            Self::CL | Self::CX => 0b001,
+            Self::DL | Self::DX => 0b010,
+            Self::BL | Self::BX => 0b011,
+            Self::AH | Self::SP => 0b100,
+            Self::CH | Self::BP => 0b101,
+            Self::DH | Self::SI => 0b110,
+            Self::BH | Self::DI => 0b111,
+        }
+    }
 
-            (true, false, true, true) => Self::BP,
+    pub fn is_wide(&self) -> bool {
+        !matches!(
+            self,
+            Self::AL | Self::AH | Self::BL | Self::BH | Self::CL | Self::CH | Self::DL | Self::DH
+        )
+    }
 
-            (false, true, false, false) => Self::DL,
-            (false, true, false, true) => Self::DX,
-            (true, true, false, false) => Self::DH,
+    pub fn is_segment(&self) -> bool {
+        matches!(self, Self::ES | Self::CS | Self::SS | Self::DS)
+    }
 
-            (true, true, false, true) => Self::SI,
-        }
+    /// Looks a register up by its assembly name, e.g. `"bx"`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        (0..8)
+            .flat_map(|bits| [Self::from_bits(bits, false), Self::from_bits(bits, true)])
+            .chain((0..4).map(Self::from_segment_bits))
+            .find(|reg| reg.to_string().eq_ignore_ascii_case(name))
     }
 }
 
@@ -0,0 +1,79 @@
+use crate::register::Register;
+
+/// The word registers, in the order traces list them.
+pub const WORD_REGISTERS: [Register; 8] = [
+    Register::AX,
+    Register::BX,
+    Register::CX,
+    Register::DX,
+    Register::SP,
+    Register::BP,
+    Register::SI,
+    Register::DI,
+];
+
+pub const SEGMENT_REGISTERS: [Register; 4] =
+    [Register::ES, Register::CS, Register::SS, Register::DS];
+
+/// Sixteen bit registers, with the 8-bit halves of ax/bx/cx/dx aliasing their low and
+/// high bytes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RegisterFile {
+    words: [u16; WORD_REGISTERS.len() + SEGMENT_REGISTERS.len()],
+}
+
+/// Which part of a word slot a register names.
+enum Part {
+    Word,
+    Low,
+    High,
+}
+
+fn locate(reg: Register) -> (usize, Part) {
+    use Register::*;
+    match reg {
+        AX => (0, Part::Word),
+        AL => (0, Part::Low),
+        AH => (0, Part::High),
+        BX => (1, Part::Word),
+        BL => (1, Part::Low),
+        BH => (1, Part::High),
+        CX => (2, Part::Word),
+        CL => (2, Part::Low),
+        CH => (2, Part::High),
+        DX => (3, Part::Word),
+        DL => (3, Part::Low),
+        DH => (3, Part::High),
+        SP => (4, Part::Word),
+        BP => (5, Part::Word),
+        SI => (6, Part::Word),
+        DI => (7, Part::Word),
+        ES => (8, Part::Word),
+        CS => (9, Part::Word),
+        SS => (10, Part::Word),
+        DS => (11, Part::Word),
+    }
+}
+
+impl RegisterFile {
+    pub fn read(&self, reg: Register) -> u16 {
+        let (slot, part) = locate(reg);
+        let word = self.words[slot];
+        match part {
+            Part::Word => word,
+            Part::Low => word & 0xff,
+            Part::High => word >> 8,
+        }
+    }
+
+    /// Writes `value`, truncated to the width of `reg`.
+    pub fn write(&mut self, reg: Register, value: u16) {
+        let (slot, part) = locate(reg);
+        let word = &mut self.words[slot];
+        *word = match part {
+            Part::Word => value,
+            Part::Low => (*word & 0xff00) | (value & 0xff),
+            Part::High => (*word & 0x00ff) | ((value & 0xff) << 8),
+        };
+    }
+}
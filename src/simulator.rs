@@ -0,0 +1,741 @@
+use std::{collections::VecDeque, fmt::Display};
+
+use crate::{
+    alu,
+    flags::Flags,
+    instruction::{Instruction, ParseInstructionError, Repeat},
+    io::Ports,
+    memory::{self, Memory},
+    opcode::Opcode,
+    operand::Operand,
+    register::Register,
+    register_file::RegisterFile,
+};
+
+#[derive(Debug)]
+pub enum SimulationError {
+    /// The bytes at the current position couldn't be decoded.
+    Decode(ParseInstructionError),
+    /// The instruction decoded fine, but the simulator can't execute it yet.
+    Unimplemented(String),
+    /// The program was still running after this many instructions.
+    StepLimit(usize),
+    /// hlt stopped the processor and no interrupt it could accept is pending.
+    Halted,
+}
+
+impl Display for SimulationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SimulationError::Decode(err) => write!(f, "{}", err),
+            SimulationError::Unimplemented(asm) => write!(f, "Unimplemented instruction: {}", asm),
+            SimulationError::StepLimit(steps) => {
+                write!(f, "Gave up after executing {} instructions.", steps)
+            }
+            SimulationError::Halted => write!(f, "Halted with no interrupt to resume."),
+        }
+    }
+}
+
+impl std::error::Error for SimulationError {}
+
+impl From<ParseInstructionError> for SimulationError {
+    fn from(err: ParseInstructionError) -> Self {
+        SimulationError::Decode(err)
+    }
+}
+
+/// Enough bytes for the longest instruction with as many prefixes as the decoder
+/// takes in front of it.
+const FETCH_BYTES: usize = crate::MAX_PREFIXES + crate::MAX_INSTRUCTION_BYTES;
+
+/// The registers, flags and ip at one point in time, without the memory, so traces
+/// can compare consecutive states cheaply.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub registers: RegisterFile,
+    pub flags: Flags,
+    pub ip: u16,
+}
+
+/// What executing an instruction did beyond changing the machine state, as far as
+/// clock estimates care.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Outcome {
+    /// A conditional jump or loop went to its target, or into raised interrupt 4.
+    pub branch_taken: bool,
+    /// A memory operand was at an odd address.
+    pub unaligned: bool,
+    /// How often a string instruction ran under a rep prefix, or how many bits a
+    /// shift moved.
+    pub repetitions: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Simulator {
+    pub registers: RegisterFile,
+    pub flags: Flags,
+    /// Offset of the next instruction within the code segment.
+    pub ip: u16,
+    pub memory: Memory,
+    /// The devices in and out reach.
+    pub ports: Ports,
+    /// Set by hlt until an external interrupt is accepted.
+    pub halted: bool,
+    /// External interrupt vectors waiting for IF to be set, oldest first.
+    pending: VecDeque<u8>,
+    /// The previous instruction was sti or loaded ss, so the next one runs before
+    /// any external interrupt is accepted.
+    interrupt_shadow: bool,
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers: self.registers,
+            flags: self.flags,
+            ip: self.ip,
+        }
+    }
+
+    /// Copies `program` to the start of the code segment, where `ip` starts out.
+    pub fn load(&mut self, program: &[u8]) {
+        let cs = self.registers.read(Register::CS);
+        self.memory.load(memory::physical(cs, 0), program);
+    }
+
+    /// The physical address of the next instruction, `cs:ip`.
+    pub fn code_address(&self) -> u32 {
+        memory::physical(self.registers.read(Register::CS), self.ip)
+    }
+
+    /// Signals an external interrupt, the way a device on the INTR line would. It's
+    /// accepted at the start of a step once IF is set, and waits behind any earlier
+    /// requests until then. Like an interrupt controller, this latches a request only
+    /// once: asking again for a vector that's still waiting does nothing. Accepting
+    /// one also ends a hlt.
+    pub fn request_interrupt(&mut self, vector: u8) {
+        if !self.pending.contains(&vector) {
+            self.pending.push_back(vector);
+        }
+    }
+
+    /// Polls the devices for interrupt requests and accepts the oldest pending one if
+    /// IF allows it, then decodes the instruction at `cs:ip`, moves `ip` past it and
+    /// executes it.
+    pub fn step(&mut self) -> Result<(Instruction, Outcome), SimulationError> {
+        for vector in self.ports.poll() {
+            self.request_interrupt(vector);
+        }
+        if !self.accept_interrupt() && self.halted {
+            return Err(SimulationError::Halted);
+        }
+        let instruction = self.fetch()?;
+        self.ip = self.ip.wrapping_add(instruction.bytes() as u16);
+        let outcome = self.execute(&instruction)?;
+        Ok((instruction, outcome))
+    }
+
+    /// Decodes the instruction at `cs:ip` without executing it.
+    pub fn fetch(&self) -> Result<Instruction, SimulationError> {
+        let cs = self.registers.read(Register::CS);
+        let mut bytes = [0; FETCH_BYTES];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.memory.read(cs, self.ip.wrapping_add(i as u16), false) as u8;
+        }
+        Instruction::try_from(&bytes[..]).map_err(|err| {
+            err.at(self.ip as usize, &bytes[..crate::MAX_INSTRUCTION_BYTES])
+                .into()
+        })
+    }
+
+    /// Executes `instruction` as though `ip` already points past it.
+    pub fn execute(&mut self, instruction: &Instruction) -> Result<Outcome, SimulationError> {
+        let mut outcome = Outcome {
+            unaligned: instruction.operands().any(
+                |operand| matches!(self.address(operand), Some((_, offset)) if offset & 1 == 1),
+            ),
+            ..Outcome::default()
+        };
+        self.apply(instruction, &mut outcome)?;
+        Ok(outcome)
+    }
+
+    fn apply(
+        &mut self,
+        instruction: &Instruction,
+        outcome: &mut Outcome,
+    ) -> Result<(), SimulationError> {
+        let unimplemented = || SimulationError::Unimplemented(instruction.to_asm());
+        match (instruction.opcode, instruction.operands) {
+            (Opcode::Mov, [Some(dest), Some(src)]) => {
+                let value = self
+                    .read(&src, instruction.wide)
+                    .ok_or_else(unimplemented)?;
+                self.shadow_if_stack_segment(&dest);
+                self.write(&dest, value, instruction.wide)
+                    .ok_or_else(unimplemented)
+            }
+            (Opcode::Push, [Some(src), None]) => {
+                // The 8086 decrements sp before reading it, so `push sp` stores the
+                // new value.
+                let value = match src {
+                    Operand::Register(Register::SP) => {
+                        self.registers.read(Register::SP).wrapping_sub(2)
+                    }
+                    _ => self.read(&src, true).ok_or_else(unimplemented)?,
+                };
+                self.push(value);
+                Ok(())
+            }
+            (Opcode::Pop, [Some(dest), None]) => {
+                let value = self.pop();
+                self.shadow_if_stack_segment(&dest);
+                self.write(&dest, value, true).ok_or_else(unimplemented)
+            }
+            (Opcode::In, [Some(dest), Some(port)]) => {
+                let port = self.read(&port, true).ok_or_else(unimplemented)?;
+                let value = self.ports.read(port, instruction.wide);
+                self.write(&dest, value, instruction.wide)
+                    .ok_or_else(unimplemented)
+            }
+            (Opcode::Out, [Some(port), Some(src)]) => {
+                let port = self.read(&port, true).ok_or_else(unimplemented)?;
+                let value = self
+                    .read(&src, instruction.wide)
+                    .ok_or_else(unimplemented)?;
+                self.ports.write(port, value, instruction.wide);
+                Ok(())
+            }
+            (Opcode::Xchg, [Some(a), Some(b)]) => {
+                let wide = instruction.wide;
+                let first = self.read(&a, wide).ok_or_else(unimplemented)?;
+                let second = self.read(&b, wide).ok_or_else(unimplemented)?;
+                self.write(&a, second, wide).ok_or_else(unimplemented)?;
+                self.write(&b, first, wide).ok_or_else(unimplemented)
+            }
+            (Opcode::Xlat, [None, None]) => {
+                // al indexes a table of bytes at ds:bx.
+                let segment = self
+                    .registers
+                    .read(instruction.segment.unwrap_or(Register::DS));
+                let offset = self
+                    .registers
+                    .read(Register::BX)
+                    .wrapping_add(self.registers.read(Register::AL));
+                let value = self.memory.read(segment, offset, false);
+                self.registers.write(Register::AL, value);
+                Ok(())
+            }
+            (Opcode::Lea, [Some(dest), Some(src)]) => {
+                let (_, offset) = self.address(&src).ok_or_else(unimplemented)?;
+                self.write(&dest, offset, true).ok_or_else(unimplemented)
+            }
+            (op @ (Opcode::Lds | Opcode::Les), [Some(dest), Some(src)]) => {
+                let (segment, offset) = self.far_pointer(&src).ok_or_else(unimplemented)?;
+                self.write(&dest, offset, true).ok_or_else(unimplemented)?;
+                let segment_register = if op == Opcode::Lds {
+                    Register::DS
+                } else {
+                    Register::ES
+                };
+                self.registers.write(segment_register, segment);
+                Ok(())
+            }
+            (Opcode::Lahf, [None, None]) => {
+                let flags = self.flags & Flags::LOW_BYTE;
+                self.registers.write(Register::AH, flags.bits());
+                Ok(())
+            }
+            (Opcode::Sahf, [None, None]) => {
+                let ah = self.registers.read(Register::AH);
+                self.flags.update(Flags::LOW_BYTE, Flags::from_bits(ah));
+                Ok(())
+            }
+            (Opcode::Pushf, [None, None]) => {
+                self.push(self.flags.bits());
+                Ok(())
+            }
+            (Opcode::Popf, [None, None]) => {
+                self.flags = Flags::from_bits(self.pop()) & Flags::ALL;
+                Ok(())
+            }
+            (op @ (Opcode::Call | Opcode::Jmp), [Some(target), None]) => {
+                let cs = self.registers.read(Register::CS);
+                let (segment, offset) = match target {
+                    Operand::RelativeJump(disp) => (cs, self.ip.wrapping_add(disp as u16)),
+                    Operand::Intersegment { segment, offset } => (segment, offset),
+                    _ if instruction.far => self.far_pointer(&target).ok_or_else(unimplemented)?,
+                    _ => (cs, self.read(&target, true).ok_or_else(unimplemented)?),
+                };
+                if op == Opcode::Call {
+                    if instruction.far {
+                        self.push(cs);
+                    }
+                    self.push(self.ip);
+                }
+                self.registers.write(Register::CS, segment);
+                self.ip = offset;
+                Ok(())
+            }
+            (op @ (Opcode::Ret | Opcode::Retf), [release, None]) => {
+                self.ip = self.pop();
+                if op == Opcode::Retf {
+                    let cs = self.pop();
+                    self.registers.write(Register::CS, cs);
+                }
+                // `ret n` also discards n bytes of arguments the caller pushed.
+                if let Some(Operand::Immediate(bytes)) = release {
+                    let sp = self.registers.read(Register::SP);
+                    self.registers
+                        .write(Register::SP, sp.wrapping_add(bytes as u16));
+                }
+                Ok(())
+            }
+            (
+                op @ (Opcode::Add | Opcode::Adc | Opcode::Sub | Opcode::Sbb | Opcode::Cmp),
+                [Some(dest), Some(src)],
+            ) => {
+                let wide = instruction.wide;
+                let a = self.read(&dest, wide).ok_or_else(unimplemented)?;
+                let b = self.read(&src, wide).ok_or_else(unimplemented)?;
+                let carry = self.flags.contains(Flags::CARRY);
+                let (result, flags) = match op {
+                    Opcode::Add => alu::add(a, b, false, wide),
+                    Opcode::Adc => alu::add(a, b, carry, wide),
+                    Opcode::Sbb => alu::sub(a, b, carry, wide),
+                    _ => alu::sub(a, b, false, wide),
+                };
+                self.flags.update(Flags::ARITHMETIC, flags);
+                if op != Opcode::Cmp {
+                    self.write(&dest, result, wide).ok_or_else(unimplemented)?;
+                }
+                Ok(())
+            }
+            (op @ (Opcode::Inc | Opcode::Dec), [Some(dest), None]) => {
+                let wide = instruction.wide;
+                let value = self.read(&dest, wide).ok_or_else(unimplemented)?;
+                let (result, flags) = if op == Opcode::Inc {
+                    alu::add(value, 1, false, wide)
+                } else {
+                    alu::sub(value, 1, false, wide)
+                };
+                // Unlike add and sub, these leave CF alone.
+                self.flags.update(Flags::ARITHMETIC & !Flags::CARRY, flags);
+                self.write(&dest, result, wide).ok_or_else(unimplemented)
+            }
+            (Opcode::Neg, [Some(dest), None]) => {
+                let wide = instruction.wide;
+                let value = self.read(&dest, wide).ok_or_else(unimplemented)?;
+                let (result, flags) = alu::sub(0, value, false, wide);
+                self.flags.update(Flags::ARITHMETIC, flags);
+                self.write(&dest, result, wide).ok_or_else(unimplemented)
+            }
+            (op @ (Opcode::Mul | Opcode::Imul), [Some(src), None]) => {
+                let wide = instruction.wide;
+                let b = self.read(&src, wide).ok_or_else(unimplemented)?;
+                let a = self
+                    .registers
+                    .read(if wide { Register::AX } else { Register::AL });
+                let (product, flags) = alu::multiply(a, b, op == Opcode::Imul, wide);
+                // A byte product fills ax, a word product dx:ax.
+                self.registers.write(Register::AX, product as u16);
+                if wide {
+                    self.registers.write(Register::DX, (product >> 16) as u16);
+                }
+                self.flags.update(Flags::CARRY | Flags::OVERFLOW, flags);
+                Ok(())
+            }
+            (op @ (Opcode::Div | Opcode::Idiv), [Some(src), None]) => {
+                let wide = instruction.wide;
+                let divisor = self.read(&src, wide).ok_or_else(unimplemented)?;
+                let ax = self.registers.read(Register::AX);
+                // A byte divides ax into al, remainder ah; a word divides dx:ax into
+                // ax, remainder dx.
+                let (dividend, quotient, remainder) = if wide {
+                    let dx = self.registers.read(Register::DX);
+                    (((dx as u32) << 16) | ax as u32, Register::AX, Register::DX)
+                } else {
+                    (ax as u32, Register::AL, Register::AH)
+                };
+                match alu::divide(dividend, divisor, op == Opcode::Idiv, wide) {
+                    Some((q, r)) => {
+                        self.registers.write(quotient, q);
+                        self.registers.write(remainder, r);
+                    }
+                    None => self.interrupt(0),
+                }
+                Ok(())
+            }
+            (
+                op @ (Opcode::Aaa
+                | Opcode::Aas
+                | Opcode::Aam
+                | Opcode::Aad
+                | Opcode::Daa
+                | Opcode::Das),
+                [None, None],
+            ) => {
+                let ax = self.registers.read(Register::AX);
+                let (ax, flags, defined) = alu::adjust(op, ax, self.flags);
+                self.registers.write(Register::AX, ax);
+                self.flags.update(defined, flags);
+                Ok(())
+            }
+            (Opcode::Cbw, [None, None]) => {
+                let al = self.registers.read(Register::AL);
+                self.registers
+                    .write(Register::AX, al as u8 as i8 as i16 as u16);
+                Ok(())
+            }
+            (Opcode::Cwd, [None, None]) => {
+                let negative = self.registers.read(Register::AX) & 0x8000 != 0;
+                self.registers
+                    .write(Register::DX, if negative { 0xffff } else { 0 });
+                Ok(())
+            }
+            (Opcode::Int, [Some(Operand::Immediate(vector)), None]) => {
+                self.interrupt(vector as u8);
+                Ok(())
+            }
+            (Opcode::Int3, [None, None]) => {
+                self.interrupt(3);
+                Ok(())
+            }
+            (Opcode::Into, [None, None]) => {
+                outcome.branch_taken = self.flags.contains(Flags::OVERFLOW);
+                if outcome.branch_taken {
+                    self.interrupt(4);
+                }
+                Ok(())
+            }
+            (Opcode::Iret, [None, None]) => {
+                self.ip = self.pop();
+                let cs = self.pop();
+                self.registers.write(Register::CS, cs);
+                self.flags = Flags::from_bits(self.pop()) & Flags::ALL;
+                Ok(())
+            }
+            (op @ (Opcode::Cli | Opcode::Sti), [None, None]) => {
+                self.flags.set(Flags::INTERRUPT, op == Opcode::Sti);
+                self.interrupt_shadow = op == Opcode::Sti;
+                Ok(())
+            }
+            (Opcode::Hlt, [None, None]) => {
+                self.halted = true;
+                Ok(())
+            }
+            // Nothing ever drives the TEST pin, so there's nothing to wait for.
+            (Opcode::Wait, [None, None]) => Ok(()),
+            // There's no coprocessor to hand the opcode to.
+            (Opcode::Esc, _) => Ok(()),
+            (op @ (Opcode::Clc | Opcode::Stc), [None, None]) => {
+                self.flags.set(Flags::CARRY, op == Opcode::Stc);
+                Ok(())
+            }
+            (Opcode::Cmc, [None, None]) => {
+                let carry = self.flags.contains(Flags::CARRY);
+                self.flags.set(Flags::CARRY, !carry);
+                Ok(())
+            }
+            (op @ (Opcode::Cld | Opcode::Std), [None, None]) => {
+                self.flags.set(Flags::DIRECTION, op == Opcode::Std);
+                Ok(())
+            }
+            (
+                op @ (Opcode::And | Opcode::Or | Opcode::Xor | Opcode::Test),
+                [Some(dest), Some(src)],
+            ) => {
+                let wide = instruction.wide;
+                let a = self.read(&dest, wide).ok_or_else(unimplemented)?;
+                let b = self.read(&src, wide).ok_or_else(unimplemented)?;
+                let result = match op {
+                    Opcode::Or => a | b,
+                    Opcode::Xor => a ^ b,
+                    _ => a & b,
+                };
+                // Besides the result flags, this clears CF, OF and AF.
+                self.flags
+                    .update(Flags::ARITHMETIC, alu::result_flags(result, wide));
+                if op != Opcode::Test {
+                    self.write(&dest, result, wide).ok_or_else(unimplemented)?;
+                }
+                Ok(())
+            }
+            (Opcode::Not, [Some(dest), None]) => {
+                let wide = instruction.wide;
+                let value = self.read(&dest, wide).ok_or_else(unimplemented)?;
+                self.write(&dest, !value, wide).ok_or_else(unimplemented)
+            }
+            (op, [Some(dest), Some(count)]) if op.is_shift() => {
+                let wide = instruction.wide;
+                let value = self.read(&dest, wide).ok_or_else(unimplemented)?;
+                let count = self.read(&count, false).ok_or_else(unimplemented)? as u8;
+                let carry = self.flags.contains(Flags::CARRY);
+                let (result, flags, defined) = alu::shift(op, value, count, carry, wide);
+                self.flags.update(defined, flags);
+                outcome.repetitions = count as u32;
+                self.write(&dest, result, wide).ok_or_else(unimplemented)
+            }
+            (op, [None, None]) if op.is_string() => {
+                self.string(instruction, outcome);
+                Ok(())
+            }
+            (op, [Some(Operand::RelativeJump(disp)), None]) => {
+                let cx = self.registers.read(Register::CX);
+                let taken = match op {
+                    Opcode::Loop | Opcode::Loopz | Opcode::Loopnz => {
+                        let cx = cx.wrapping_sub(1);
+                        self.registers.write(Register::CX, cx);
+                        let zero = self.flags.contains(Flags::ZERO);
+                        cx != 0
+                            && match op {
+                                Opcode::Loopz => zero,
+                                Opcode::Loopnz => !zero,
+                                _ => true,
+                            }
+                    }
+                    Opcode::Jcxz => cx == 0,
+                    _ => self.condition(op).ok_or_else(unimplemented)?,
+                };
+                outcome.branch_taken = taken;
+                if taken {
+                    self.ip = self.ip.wrapping_add(disp as u16);
+                }
+                Ok(())
+            }
+            _ => Err(unimplemented()),
+        }
+    }
+
+    /// Runs a string instruction once, or as long as its repeat prefix asks for.
+    fn string(&mut self, instruction: &Instruction, outcome: &mut Outcome) {
+        let Some(repeat) = instruction.repeat else {
+            self.string_once(instruction);
+            return;
+        };
+        while self.registers.read(Register::CX) != 0 {
+            self.string_once(instruction);
+            let cx = self.registers.read(Register::CX).wrapping_sub(1);
+            self.registers.write(Register::CX, cx);
+            outcome.repetitions += 1;
+            // Only the comparisons stop early: repe once they differ, repne once
+            // they match.
+            let compares = matches!(instruction.opcode, Opcode::Cmps | Opcode::Scas);
+            if compares && self.flags.contains(Flags::ZERO) != (repeat == Repeat::Rep) {
+                break;
+            }
+        }
+    }
+
+    /// One step of a string instruction. The source is `ds:si` unless overridden, the
+    /// destination always `es:di`, and both move backwards when DF is set.
+    fn string_once(&mut self, instruction: &Instruction) {
+        let wide = instruction.wide;
+        let source = self
+            .registers
+            .read(instruction.segment.unwrap_or(Register::DS));
+        let es = self.registers.read(Register::ES);
+        let (si, di) = (
+            self.registers.read(Register::SI),
+            self.registers.read(Register::DI),
+        );
+        let accumulator = if wide { Register::AX } else { Register::AL };
+        let (uses_si, uses_di) = match instruction.opcode {
+            Opcode::Movs => {
+                let value = self.memory.read(source, si, wide);
+                self.memory.write(es, di, value, wide);
+                (true, true)
+            }
+            Opcode::Cmps => {
+                let (a, b) = (
+                    self.memory.read(source, si, wide),
+                    self.memory.read(es, di, wide),
+                );
+                self.flags
+                    .update(Flags::ARITHMETIC, alu::sub(a, b, false, wide).1);
+                (true, true)
+            }
+            Opcode::Scas => {
+                let a = self.registers.read(accumulator);
+                let b = self.memory.read(es, di, wide);
+                self.flags
+                    .update(Flags::ARITHMETIC, alu::sub(a, b, false, wide).1);
+                (false, true)
+            }
+            Opcode::Lods => {
+                let value = self.memory.read(source, si, wide);
+                self.registers.write(accumulator, value);
+                (true, false)
+            }
+            _ => {
+                let value = self.registers.read(accumulator);
+                self.memory.write(es, di, value, wide);
+                (false, true)
+            }
+        };
+
+        let size: u16 = if wide { 2 } else { 1 };
+        let step = if self.flags.contains(Flags::DIRECTION) {
+            size.wrapping_neg()
+        } else {
+            size
+        };
+        if uses_si {
+            self.registers.write(Register::SI, si.wrapping_add(step));
+        }
+        if uses_di {
+            self.registers.write(Register::DI, di.wrapping_add(step));
+        }
+    }
+
+    /// Accepts the oldest pending external interrupt, unless IF is clear or the
+    /// previous instruction holds interrupts off for one more.
+    fn accept_interrupt(&mut self) -> bool {
+        let shadow = std::mem::take(&mut self.interrupt_shadow);
+        if shadow || !self.flags.contains(Flags::INTERRUPT) {
+            return false;
+        }
+        let Some(vector) = self.pending.pop_front() else {
+            return false;
+        };
+        self.halted = false;
+        self.interrupt(vector);
+        true
+    }
+
+    /// Loading ss is followed by loading sp, so the 8086 won't take an interrupt in
+    /// between, when the stack pointer is only half switched.
+    fn shadow_if_stack_segment(&mut self, dest: &Operand) {
+        if *dest == Operand::Register(Register::SS) {
+            self.interrupt_shadow = true;
+        }
+    }
+
+    /// Transfers control through the interrupt vector table at `0000:0000` the way
+    /// the 8086 does: flags, cs and ip go on the stack, IF and TF are cleared, and
+    /// cs:ip are loaded from the vector's far pointer.
+    fn interrupt(&mut self, vector: u8) {
+        self.push(self.flags.bits());
+        self.flags.set(Flags::INTERRUPT | Flags::TRAP, false);
+        self.push(self.registers.read(Register::CS));
+        self.push(self.ip);
+        let entry = vector as u16 * 4;
+        self.ip = self.memory.read(0, entry, true);
+        let cs = self.memory.read(0, entry.wrapping_add(2), true);
+        self.registers.write(Register::CS, cs);
+    }
+
+    /// Stores a word at `ss:sp` after making room for it.
+    fn push(&mut self, value: u16) {
+        let sp = self.registers.read(Register::SP).wrapping_sub(2);
+        self.registers.write(Register::SP, sp);
+        let ss = self.registers.read(Register::SS);
+        self.memory.write(ss, sp, value, true);
+    }
+
+    /// Loads the word at `ss:sp` and releases its space.
+    fn pop(&mut self) -> u16 {
+        let (ss, sp) = (
+            self.registers.read(Register::SS),
+            self.registers.read(Register::SP),
+        );
+        self.registers.write(Register::SP, sp.wrapping_add(2));
+        self.memory.read(ss, sp, true)
+    }
+
+    /// The `(segment, offset)` a far pointer in memory holds: the offset comes first,
+    /// followed by the segment.
+    fn far_pointer(&self, operand: &Operand) -> Option<(u16, u16)> {
+        let (segment, offset) = self.address(operand)?;
+        Some((
+            self.memory.read(segment, offset.wrapping_add(2), true),
+            self.memory.read(segment, offset, true),
+        ))
+    }
+
+    /// Whether the flags satisfy a conditional jump, or `None` if `op` isn't one.
+    fn condition(&self, op: Opcode) -> Option<bool> {
+        let flag = |flag| self.flags.contains(flag);
+        let (carry, zero, sign) = (flag(Flags::CARRY), flag(Flags::ZERO), flag(Flags::SIGN));
+        let (overflow, parity) = (flag(Flags::OVERFLOW), flag(Flags::PARITY));
+        Some(match op {
+            Opcode::Jo => overflow,
+            Opcode::Jno => !overflow,
+            Opcode::Jb => carry,
+            Opcode::Jnb => !carry,
+            Opcode::Je => zero,
+            Opcode::Jne => !zero,
+            Opcode::Jbe => carry || zero,
+            Opcode::Ja => !carry && !zero,
+            Opcode::Js => sign,
+            Opcode::Jns => !sign,
+            Opcode::Jp => parity,
+            Opcode::Jnp => !parity,
+            Opcode::Jl => sign != overflow,
+            Opcode::Jnl => sign == overflow,
+            Opcode::Jle => zero || sign != overflow,
+            Opcode::Jg => !zero && sign == overflow,
+            _ => return None,
+        })
+    }
+
+    /// The segment and offset a memory operand refers to. Addresses based on bp
+    /// default to the stack segment, everything else to the data segment.
+    fn address(&self, operand: &Operand) -> Option<(u16, u16)> {
+        let Operand::Memory {
+            base,
+            index,
+            disp,
+            segment,
+        } = *operand
+        else {
+            return None;
+        };
+        let offset = [base, index]
+            .into_iter()
+            .flatten()
+            .fold(disp as u16, |offset, reg| {
+                offset.wrapping_add(self.registers.read(reg))
+            });
+        let segment = segment.unwrap_or(if base == Some(Register::BP) {
+            Register::SS
+        } else {
+            Register::DS
+        });
+        Some((self.registers.read(segment), offset))
+    }
+
+    /// Returns `None` for operands the simulator can't read yet.
+    fn read(&self, operand: &Operand, wide: bool) -> Option<u16> {
+        match *operand {
+            Operand::Register(reg) => Some(self.registers.read(reg)),
+            Operand::Immediate(value) => Some(value as u16),
+            Operand::Memory { .. } => {
+                let (segment, offset) = self.address(operand)?;
+                Some(self.memory.read(segment, offset, wide))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns `None` for operands the simulator can't write yet.
+    fn write(&mut self, operand: &Operand, value: u16, wide: bool) -> Option<()> {
+        match *operand {
+            Operand::Register(reg) => {
+                self.registers.write(reg, value);
+                Some(())
+            }
+            Operand::Memory { .. } => {
+                let (segment, offset) = self.address(operand)?;
+                self.memory.write(segment, offset, value, wide);
+                Some(())
+            }
+            _ => None,
+        }
+    }
+}
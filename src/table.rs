@@ -0,0 +1,317 @@
+//! The 8086 instruction encodings, transcribed from table 4-12 of the 8086 family
+//! user's manual in the same spirit as `sim86_instruction_table.inl`.
+//!
+//! Each encoding is a list of fields read most significant bit first. Literal fields
+//! have to match for the encoding to apply, the named fields are captured for the
+//! decoder, and the marker fields (`DISP`, `ADDR`, `DATA`, ...) describe the bytes
+//! that follow the bit fields. Displacements implied by the mod field need no marker.
+
+use std::sync::OnceLock;
+
+use crate::opcode::Opcode;
+
+/// The bit fields an encoding can capture.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Slot {
+    D,
+    S,
+    W,
+    Mod,
+    Reg,
+    Rm,
+    /// A segment register.
+    Sr,
+    /// Shift by cl rather than by one.
+    V,
+    /// The upper three bits of the opcode esc hands to a coprocessor. The reg field
+    /// holds the lower three.
+    Esc,
+}
+
+pub const SLOT_COUNT: usize = 9;
+
+impl Slot {
+    pub const fn width(self) -> u8 {
+        match self {
+            Slot::D | Slot::S | Slot::W | Slot::V => 1,
+            Slot::Mod | Slot::Sr => 2,
+            Slot::Reg | Slot::Rm | Slot::Esc => 3,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Field {
+    /// Opcode bits that have to match exactly.
+    Literal { value: u8, count: u8 },
+    /// Bits read from the instruction stream into a slot.
+    Bits(Slot),
+    /// A slot value the encoding implies without spending any bits on it.
+    Implicit(Slot, u8),
+    /// An eight bit displacement follows even though there is no mod field.
+    Disp,
+    /// A sixteen bit address follows, regardless of mod and w.
+    Addr,
+    /// The displacement is a jump target relative to the end of the instruction.
+    RelJmp,
+    /// Immediate data follows, sixteen bits wide when w is set and s is not.
+    Data,
+    /// Immediate data follows only when w is set.
+    DataIfW,
+    /// The instruction transfers control to another segment.
+    Far,
+    /// The operand besides the accumulator is an I/O port: the data byte when there
+    /// is one, dx otherwise.
+    Port,
+}
+
+pub const D: Field = Field::Bits(Slot::D);
+pub const S: Field = Field::Bits(Slot::S);
+pub const W: Field = Field::Bits(Slot::W);
+pub const V: Field = Field::Bits(Slot::V);
+pub const MOD: Field = Field::Bits(Slot::Mod);
+pub const REG: Field = Field::Bits(Slot::Reg);
+pub const RM: Field = Field::Bits(Slot::Rm);
+pub const SR: Field = Field::Bits(Slot::Sr);
+pub const ESC: Field = Field::Bits(Slot::Esc);
+pub const DISP: Field = Field::Disp;
+pub const ADDR: Field = Field::Addr;
+pub const REL_JMP: Field = Field::RelJmp;
+pub const DATA: Field = Field::Data;
+pub const DATA_IF_W: Field = Field::DataIfW;
+pub const FAR: Field = Field::Far;
+pub const PORT: Field = Field::Port;
+
+/// Opcode bits written out the way the manual does, e.g. `lit("100010")`.
+pub const fn lit(pattern: &str) -> Field {
+    let bits = pattern.as_bytes();
+    let mut value = 0;
+    let mut i = 0;
+    while i < bits.len() {
+        value = (value << 1) | (bits[i] - b'0');
+        i += 1;
+    }
+    Field::Literal {
+        value,
+        count: bits.len() as u8,
+    }
+}
+
+pub const fn imp(slot: Slot, value: u8) -> Field {
+    Field::Implicit(slot, value)
+}
+
+pub struct Encoding {
+    pub opcode: Opcode,
+    pub fields: &'static [Field],
+}
+
+const fn enc(opcode: Opcode, fields: &'static [Field]) -> Encoding {
+    Encoding { opcode, fields }
+}
+
+use Opcode::*;
+use Slot::{Mod as ModSlot, Reg as RegSlot, Rm as RmSlot, D as DSlot, W as WSlot};
+
+#[rustfmt::skip]
+pub static ENCODINGS: &[Encoding] = &[
+    enc(Mov, &[lit("100010"), D, W, MOD, REG, RM]),
+    enc(Mov, &[lit("1100011"), W, MOD, lit("000"), RM, DATA, DATA_IF_W]),
+    enc(Mov, &[lit("1011"), W, REG, DATA, DATA_IF_W, imp(DSlot, 1)]),
+    enc(Mov, &[lit("1010000"), W, ADDR, imp(RegSlot, 0), imp(DSlot, 1)]),
+    enc(Mov, &[lit("1010001"), W, ADDR, imp(RegSlot, 0), imp(DSlot, 0)]),
+    enc(Mov, &[lit("10001110"), MOD, lit("0"), SR, RM, imp(DSlot, 1), imp(WSlot, 1)]),
+    enc(Mov, &[lit("10001100"), MOD, lit("0"), SR, RM, imp(DSlot, 0), imp(WSlot, 1)]),
+
+    enc(In, &[lit("1110010"), W, DATA, PORT, imp(RegSlot, 0), imp(DSlot, 1)]),
+    enc(In, &[lit("1110110"), W, PORT, imp(RegSlot, 0), imp(DSlot, 1)]),
+    enc(Out, &[lit("1110011"), W, DATA, PORT, imp(RegSlot, 0), imp(DSlot, 0)]),
+    enc(Out, &[lit("1110111"), W, PORT, imp(RegSlot, 0), imp(DSlot, 0)]),
+
+    enc(Push, &[lit("11111111"), MOD, lit("110"), RM, imp(WSlot, 1)]),
+    enc(Push, &[lit("01010"), REG, imp(WSlot, 1)]),
+    enc(Push, &[lit("000"), SR, lit("110"), imp(WSlot, 1)]),
+
+    enc(Pop, &[lit("10001111"), MOD, lit("000"), RM, imp(WSlot, 1)]),
+    enc(Pop, &[lit("01011"), REG, imp(WSlot, 1)]),
+    enc(Pop, &[lit("000"), SR, lit("111"), imp(WSlot, 1)]),
+
+    enc(Xchg, &[lit("1000011"), W, MOD, REG, RM, imp(DSlot, 1)]),
+    enc(Xchg, &[lit("10010"), REG, imp(ModSlot, 0b11), imp(RmSlot, 0), imp(WSlot, 1)]),
+
+    enc(Xlat, &[lit("11010111")]),
+    enc(Lea, &[lit("10001101"), MOD, REG, RM, imp(DSlot, 1), imp(WSlot, 1)]),
+    enc(Lds, &[lit("11000101"), MOD, REG, RM, imp(DSlot, 1), imp(WSlot, 1)]),
+    enc(Les, &[lit("11000100"), MOD, REG, RM, imp(DSlot, 1), imp(WSlot, 1)]),
+    enc(Lahf, &[lit("10011111")]),
+    enc(Sahf, &[lit("10011110")]),
+    enc(Pushf, &[lit("10011100")]),
+    enc(Popf, &[lit("10011101")]),
+
+    enc(Add, &[lit("000000"), D, W, MOD, REG, RM]),
+    enc(Add, &[lit("100000"), S, W, MOD, lit("000"), RM, DATA, DATA_IF_W]),
+    enc(Add, &[lit("0000010"), W, DATA, DATA_IF_W, imp(RegSlot, 0), imp(DSlot, 1)]),
+
+    enc(Adc, &[lit("000100"), D, W, MOD, REG, RM]),
+    enc(Adc, &[lit("100000"), S, W, MOD, lit("010"), RM, DATA, DATA_IF_W]),
+    enc(Adc, &[lit("0001010"), W, DATA, DATA_IF_W, imp(RegSlot, 0), imp(DSlot, 1)]),
+
+    enc(Sub, &[lit("001010"), D, W, MOD, REG, RM]),
+    enc(Sub, &[lit("100000"), S, W, MOD, lit("101"), RM, DATA, DATA_IF_W]),
+    enc(Sub, &[lit("0010110"), W, DATA, DATA_IF_W, imp(RegSlot, 0), imp(DSlot, 1)]),
+
+    enc(Sbb, &[lit("000110"), D, W, MOD, REG, RM]),
+    enc(Sbb, &[lit("100000"), S, W, MOD, lit("011"), RM, DATA, DATA_IF_W]),
+    enc(Sbb, &[lit("0001110"), W, DATA, DATA_IF_W, imp(RegSlot, 0), imp(DSlot, 1)]),
+
+    enc(Inc, &[lit("1111111"), W, MOD, lit("000"), RM]),
+    enc(Inc, &[lit("01000"), REG, imp(WSlot, 1)]),
+    enc(Dec, &[lit("1111111"), W, MOD, lit("001"), RM]),
+    enc(Dec, &[lit("01001"), REG, imp(WSlot, 1)]),
+    enc(Neg, &[lit("1111011"), W, MOD, lit("011"), RM]),
+
+    enc(Mul, &[lit("1111011"), W, MOD, lit("100"), RM]),
+    enc(Imul, &[lit("1111011"), W, MOD, lit("101"), RM]),
+    enc(Div, &[lit("1111011"), W, MOD, lit("110"), RM]),
+    enc(Idiv, &[lit("1111011"), W, MOD, lit("111"), RM]),
+
+    // aam and aad are followed by the divisor, which the manual only lists as 10.
+    enc(Aaa, &[lit("00110111")]),
+    enc(Aas, &[lit("00111111")]),
+    enc(Aam, &[lit("11010100"), lit("00001010")]),
+    enc(Aad, &[lit("11010101"), lit("00001010")]),
+    enc(Daa, &[lit("00100111")]),
+    enc(Das, &[lit("00101111")]),
+    enc(Cbw, &[lit("10011000")]),
+    enc(Cwd, &[lit("10011001")]),
+
+    enc(And, &[lit("001000"), D, W, MOD, REG, RM]),
+    enc(And, &[lit("1000000"), W, MOD, lit("100"), RM, DATA, DATA_IF_W]),
+    enc(And, &[lit("0010010"), W, DATA, DATA_IF_W, imp(RegSlot, 0), imp(DSlot, 1)]),
+
+    // The manual shows a d bit here, but test is symmetric and 1000011 is xchg.
+    enc(Test, &[lit("1000010"), W, MOD, REG, RM]),
+    enc(Test, &[lit("1111011"), W, MOD, lit("000"), RM, DATA, DATA_IF_W]),
+    enc(Test, &[lit("1010100"), W, DATA, DATA_IF_W, imp(RegSlot, 0), imp(DSlot, 1)]),
+
+    enc(Or, &[lit("000010"), D, W, MOD, REG, RM]),
+    enc(Or, &[lit("1000000"), W, MOD, lit("001"), RM, DATA, DATA_IF_W]),
+    enc(Or, &[lit("0000110"), W, DATA, DATA_IF_W, imp(RegSlot, 0), imp(DSlot, 1)]),
+
+    enc(Xor, &[lit("001100"), D, W, MOD, REG, RM]),
+    enc(Xor, &[lit("1000000"), W, MOD, lit("110"), RM, DATA, DATA_IF_W]),
+    enc(Xor, &[lit("0011010"), W, DATA, DATA_IF_W, imp(RegSlot, 0), imp(DSlot, 1)]),
+
+    enc(Not, &[lit("1111011"), W, MOD, lit("010"), RM]),
+
+    enc(Shl, &[lit("110100"), V, W, MOD, lit("100"), RM]),
+    enc(Shr, &[lit("110100"), V, W, MOD, lit("101"), RM]),
+    enc(Sar, &[lit("110100"), V, W, MOD, lit("111"), RM]),
+    enc(Rol, &[lit("110100"), V, W, MOD, lit("000"), RM]),
+    enc(Ror, &[lit("110100"), V, W, MOD, lit("001"), RM]),
+    enc(Rcl, &[lit("110100"), V, W, MOD, lit("010"), RM]),
+    enc(Rcr, &[lit("110100"), V, W, MOD, lit("011"), RM]),
+
+    enc(Cmp, &[lit("001110"), D, W, MOD, REG, RM]),
+    enc(Cmp, &[lit("100000"), S, W, MOD, lit("111"), RM, DATA, DATA_IF_W]),
+    enc(Cmp, &[lit("0011110"), W, DATA, DATA_IF_W, imp(RegSlot, 0), imp(DSlot, 1)]),
+
+    enc(Jo, &[lit("01110000"), DISP, REL_JMP]),
+    enc(Jno, &[lit("01110001"), DISP, REL_JMP]),
+    enc(Jb, &[lit("01110010"), DISP, REL_JMP]),
+    enc(Jnb, &[lit("01110011"), DISP, REL_JMP]),
+    enc(Je, &[lit("01110100"), DISP, REL_JMP]),
+    enc(Jne, &[lit("01110101"), DISP, REL_JMP]),
+    enc(Jbe, &[lit("01110110"), DISP, REL_JMP]),
+    enc(Ja, &[lit("01110111"), DISP, REL_JMP]),
+    enc(Js, &[lit("01111000"), DISP, REL_JMP]),
+    enc(Jns, &[lit("01111001"), DISP, REL_JMP]),
+    enc(Jp, &[lit("01111010"), DISP, REL_JMP]),
+    enc(Jnp, &[lit("01111011"), DISP, REL_JMP]),
+    enc(Jl, &[lit("01111100"), DISP, REL_JMP]),
+    enc(Jnl, &[lit("01111101"), DISP, REL_JMP]),
+    enc(Jle, &[lit("01111110"), DISP, REL_JMP]),
+    enc(Jg, &[lit("01111111"), DISP, REL_JMP]),
+    enc(Loopnz, &[lit("11100000"), DISP, REL_JMP]),
+    enc(Loopz, &[lit("11100001"), DISP, REL_JMP]),
+    enc(Loop, &[lit("11100010"), DISP, REL_JMP]),
+    enc(Jcxz, &[lit("11100011"), DISP, REL_JMP]),
+
+    enc(Call, &[lit("11101000"), ADDR, REL_JMP]),
+    enc(Call, &[lit("11111111"), MOD, lit("010"), RM, imp(WSlot, 1)]),
+    enc(Call, &[lit("10011010"), ADDR, DATA, DATA_IF_W, imp(WSlot, 1), FAR]),
+    enc(Call, &[lit("11111111"), MOD, lit("011"), RM, imp(WSlot, 1), FAR]),
+
+    enc(Jmp, &[lit("11101001"), ADDR, REL_JMP]),
+    enc(Jmp, &[lit("11101011"), DISP, REL_JMP]),
+    enc(Jmp, &[lit("11111111"), MOD, lit("100"), RM, imp(WSlot, 1)]),
+    enc(Jmp, &[lit("11101010"), ADDR, DATA, DATA_IF_W, imp(WSlot, 1), FAR]),
+    enc(Jmp, &[lit("11111111"), MOD, lit("101"), RM, imp(WSlot, 1), FAR]),
+
+    // The manual calls both of these ret; nasm wants retf for the far ones.
+    enc(Ret, &[lit("11000011")]),
+    enc(Ret, &[lit("11000010"), DATA, DATA_IF_W, imp(WSlot, 1)]),
+    enc(Retf, &[lit("11001011"), FAR]),
+    enc(Retf, &[lit("11001010"), DATA, DATA_IF_W, imp(WSlot, 1), FAR]),
+
+    enc(Movs, &[lit("1010010"), W]),
+    enc(Cmps, &[lit("1010011"), W]),
+    enc(Scas, &[lit("1010111"), W]),
+    enc(Lods, &[lit("1010110"), W]),
+    enc(Stos, &[lit("1010101"), W]),
+    enc(Cld, &[lit("11111100")]),
+    enc(Std, &[lit("11111101")]),
+
+    enc(Int, &[lit("11001101"), DATA]),
+    // The manual has no int3 mnemonic for the one byte form, but nasm does.
+    enc(Int3, &[lit("11001100")]),
+    enc(Into, &[lit("11001110")]),
+    enc(Iret, &[lit("11001111")]),
+    enc(Cli, &[lit("11111010")]),
+    enc(Sti, &[lit("11111011")]),
+    enc(Hlt, &[lit("11110100")]),
+    enc(Wait, &[lit("10011011")]),
+    enc(Clc, &[lit("11111000")]),
+    enc(Cmc, &[lit("11110101")]),
+    enc(Stc, &[lit("11111001")]),
+    // nasm doesn't know esc, so `esc 45, [bx]` only ever comes from here.
+    enc(Esc, &[lit("11011"), ESC, MOD, REG, RM, imp(DSlot, 1)]),
+];
+
+/// For every possible first byte, the encodings whose leading literal bits match it,
+/// in table order. Built once so decoding never scans the whole table.
+pub fn candidates(first: u8) -> &'static [u16] {
+    static DISPATCH: OnceLock<Vec<Vec<u16>>> = OnceLock::new();
+    let dispatch = DISPATCH.get_or_init(|| {
+        (0..=255u8)
+            .map(|byte| {
+                (0..ENCODINGS.len() as u16)
+                    .filter(|&index| first_byte_matches(&ENCODINGS[index as usize], byte))
+                    .collect()
+            })
+            .collect()
+    });
+    &dispatch[first as usize]
+}
+
+fn first_byte_matches(encoding: &Encoding, byte: u8) -> bool {
+    let mut used = 0;
+    for field in encoding.fields {
+        let width = match *field {
+            Field::Literal { value, count } => {
+                let shift = 8 - used - count;
+                if (byte as u16 >> shift) & ((1 << count) - 1) != value as u16 {
+                    return false;
+                }
+                count
+            }
+            Field::Bits(slot) => slot.width(),
+            _ => 0,
+        };
+        used += width;
+        if used >= 8 {
+            break;
+        }
+    }
+    true
+}
@@ -0,0 +1,105 @@
+//! Formats instructions and simulator state the way the reference sim86 prints them,
+//! so traces can be diffed against the `listing_00xx.txt` files.
+//!
+//! This differs from [`Instruction::to_asm`], which produces nasm input: memory
+//! operands are written without spaces (`[bx+si+4]`), direct addresses carry a sign
+//! (`[+1000]`), and immediates keep the value they were encoded with.
+
+use crate::{
+    instruction::Instruction,
+    opcode::Opcode,
+    operand::Operand,
+    register_file::{SEGMENT_REGISTERS, WORD_REGISTERS},
+    simulator::Snapshot,
+};
+
+pub fn instruction_text(instruction: &Instruction) -> String {
+    // sim86 only shows an override inside a memory operand, and drops any other. It
+    // also swaps the operands of every lock xchg, where the decoder only swaps the ones
+    // nasm needs swapped, with memory first.
+    let mut instruction = Instruction {
+        segment: None,
+        ..instruction.clone()
+    };
+    if instruction.lock
+        && instruction.opcode == Opcode::Xchg
+        && !instruction.operands().any(Operand::is_memory)
+    {
+        instruction.operands.swap(0, 1);
+    }
+    let instruction = &instruction;
+    let first_is_register = matches!(instruction.operands[0], Some(Operand::Register(_)));
+    let operands: Vec<String> = instruction
+        .operands()
+        .map(|operand| match *operand {
+            Operand::Register(reg) => reg.to_string(),
+            Operand::Memory {
+                base,
+                index,
+                disp,
+                segment,
+            } => {
+                let far = if instruction.far { "far " } else { "" };
+                let size = match (first_is_register, instruction.wide) {
+                    (true, _) => "",
+                    (false, true) => "word ",
+                    (false, false) => "byte ",
+                };
+                let terms: Vec<String> = [base, index]
+                    .into_iter()
+                    .flatten()
+                    .map(|reg| reg.to_string())
+                    .collect();
+                let disp = if terms.is_empty() || disp != 0 {
+                    format!("{:+}", disp)
+                } else {
+                    String::new()
+                };
+                let segment = segment.map(|reg| format!("{}:", reg)).unwrap_or_default();
+                format!("{}{}{}[{}{}]", far, size, segment, terms.join("+"), disp)
+            }
+            Operand::Immediate(value) => value.to_string(),
+            Operand::RelativeJump(disp) => format!("${:+}", disp as i32 + instruction.size as i32),
+            Operand::Intersegment { segment, offset } => format!("{}:{}", segment, offset),
+        })
+        .collect();
+    format!("{} {}", instruction.mnemonic(), operands.join(", "))
+}
+
+/// Lists every register `after` changed, e.g. `bx:0x0->0x1 `. Traces older than
+/// listing 0048 don't mention `ip`, hence `show_ip`.
+pub fn register_diff(before: &Snapshot, after: &Snapshot, show_ip: bool) -> String {
+    let mut diff = String::new();
+    for reg in WORD_REGISTERS.into_iter().chain(SEGMENT_REGISTERS) {
+        let (old, new) = (before.registers.read(reg), after.registers.read(reg));
+        if old != new {
+            diff.push_str(&format!("{}:{:#x}->{:#x} ", reg, old, new));
+        }
+    }
+    if show_ip && before.ip != after.ip {
+        diff.push_str(&format!("ip:{:#x}->{:#x} ", before.ip, after.ip));
+    }
+    if before.flags != after.flags {
+        diff.push_str(&format!("flags:{}->{} ", before.flags, after.flags));
+    }
+    diff
+}
+
+/// The `Final registers:` block, listing every register that isn't zero.
+pub fn final_registers(state: &Snapshot, show_ip: bool) -> String {
+    let mut text = String::from("Final registers:\n");
+    let registers = WORD_REGISTERS
+        .into_iter()
+        .chain(SEGMENT_REGISTERS)
+        .map(|reg| (reg.to_string(), state.registers.read(reg)));
+    let ip = show_ip.then(|| ("ip".to_string(), state.ip));
+    for (name, value) in registers.chain(ip) {
+        if value != 0 {
+            text.push_str(&format!("{:>8}: 0x{:04x} ({})\n", name, value, value));
+        }
+    }
+    if !state.flags.is_empty() {
+        text.push_str(&format!("{:>8}: {}\n", "flags", state.flags));
+    }
+    text
+}